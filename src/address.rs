@@ -1,25 +1,72 @@
+use crate::bech32;
+use crate::crypto::hd::{self, DerivationPath, ExtendedKey};
 use ring::signature::Ed25519KeyPair;
-use serde::{Serialize,Deserialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// A 160-bit public address.
-#[derive(Eq, PartialEq, Serialize, Deserialize, Clone, Hash, Default, Copy)]
-pub struct H160([u8; 20]); 
+#[derive(Eq, PartialEq, Clone, Hash, Default, Copy)]
+pub struct H160([u8; 20]);
 
-impl std::fmt::Display for H160 {
+/// The binary serialization of `H160`, which hashes and signatures are computed over.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "H160")]
+struct H160Bytes([u8; 20]);
+
+impl Serialize for H160 {
+    /// Serialize as text in human-readable formats such as JSON, and as raw bytes otherwise.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            H160Bytes(self.0).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for H160 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = String::deserialize(deserializer)?;
+            text.parse().map_err(serde::de::Error::custom)
+        } else {
+            H160Bytes::deserialize(deserializer).map(|bytes| H160(bytes.0))
+        }
+    }
+}
+
+/// Human-readable prefix of the text form of addresses.
+pub const ADDRESS_PREFIX: &str = "bit";
+
+/// Reasons a string is not a valid address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// Raw hex is not accepted because it has no checksum
+    Hex,
+    /// The string is not valid Bech32m, e.g. because its checksum does not match
+    Encoding(bech32::Error),
+    /// The address belongs to another network
+    WrongPrefix(String),
+    /// The address does not encode 20 bytes
+    WrongLength(usize),
+}
+
+impl std::fmt::Display for AddressError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let start = if let Some(precision) = f.precision() {
-            if precision >= 40 {
-                0
-            } else {
-                20 - precision / 2
+        match self {
+            AddressError::Hex => write!(f, "raw hex is not accepted, use the checksummed {}1... form", ADDRESS_PREFIX),
+            AddressError::Encoding(e) => write!(f, "invalid address: {}", e),
+            AddressError::WrongPrefix(prefix) => {
+                write!(f, "address prefix should be {} but is {}", ADDRESS_PREFIX, prefix)
             }
-        } else {
-            0
-        };
-        for byte_idx in start..20 {
-            write!(f, "{:>02x}", &self.0[byte_idx])?;
+            AddressError::WrongLength(length) => write!(f, "address should be 20 bytes but is {}", length),
         }
-        Ok(())
+    }
+}
+
+impl std::fmt::Display for H160 {
+    /// Format as Bech32m with the `ADDRESS_PREFIX`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&bech32::encode(ADDRESS_PREFIX, &self.0))
     }
 }
 
@@ -33,6 +80,27 @@ impl std::fmt::Debug for H160 {
     }
 }
 
+impl std::str::FromStr for H160 {
+    type Err = AddressError;
+
+    /// Parse an address in the Bech32m form printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AddressError::Hex);
+        }
+        let (prefix, data) = bech32::decode(s).map_err(AddressError::Encoding)?;
+        if prefix != ADDRESS_PREFIX {
+            return Err(AddressError::WrongPrefix(prefix));
+        }
+        if data.len() != 20 {
+            return Err(AddressError::WrongLength(data.len()));
+        }
+        let mut buffer: [u8; 20] = [0; 20];
+        buffer.copy_from_slice(&data);
+        Ok(H160(buffer))
+    }
+}
+
 impl H160 {
     /// Create a new address from a public key.
     pub fn from_pubkey(pub_key_as_bytes: &[u8]) -> Self {
@@ -59,10 +127,66 @@ impl std::convert::From<[u8; 20]> for H160 {
 }
 
 // for Initial coin offering:
-/// Get a deterministic keypair from a nonce:
-pub fn get_deterministic_keypair(nonce: u8) -> Ed25519KeyPair {
-    let mut seed = [0u8; 32];
-    seed[0] = nonce;
-    let keypair = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
-    keypair
-}
\ No newline at end of file
+/// Seed phrase of the default genesis seed. It is public, so anyone can spend the ICO coins of a
+/// network that does not configure its own genesis seed.
+pub const DEFAULT_GENESIS_PHRASE: &str = "bitcoin genesis";
+/// Number of accounts funded by the ICO
+pub const ICO_ACCOUNTS: u32 = 10;
+
+/// The genesis seed of `DEFAULT_GENESIS_PHRASE`.
+pub fn default_genesis_seed() -> [u8; 64] {
+    hd::seed_from_phrase(DEFAULT_GENESIS_PHRASE, "")
+}
+
+/// Derivation path of the `index`-th ICO account: `m/0'/<index>'`.
+pub fn ico_path(index: u32) -> DerivationPath {
+    DerivationPath::new(&[0, index])
+}
+
+/// Get the keypair of the `index`-th ICO account, derived from the genesis seed.
+pub fn ico_keypair(genesis_seed: &[u8], index: u32) -> Ed25519KeyPair {
+    ExtendedKey::master(genesis_seed).derive(&ico_path(index)).key_pair()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_roundtrip() {
+        let address = H160(hex!("000102030405060708090a0b0c0d0e0f10111213"));
+        let text = address.to_string();
+        assert!(text.starts_with("bit1"));
+        assert_eq!(text.parse::<H160>(), Ok(address));
+        assert_eq!(text.to_uppercase().parse::<H160>(), Ok(address));
+
+        // a single mistyped character is caught by the checksum
+        let mut typo = text.clone().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            String::from_utf8(typo).unwrap().parse::<H160>(),
+            Err(AddressError::Encoding(bech32::Error::InvalidChecksum))
+        );
+        assert_eq!(hex::encode(address.0).parse::<H160>(), Err(AddressError::Hex));
+        assert_eq!(
+            bech32::encode("tb", &address.0).parse::<H160>(),
+            Err(AddressError::WrongPrefix("tb".to_string()))
+        );
+        assert_eq!(
+            bech32::encode(ADDRESS_PREFIX, &[0; 19]).parse::<H160>(),
+            Err(AddressError::WrongLength(19))
+        );
+    }
+
+    #[test]
+    fn serialization() {
+        let address = H160(hex!("000102030405060708090a0b0c0d0e0f10111213"));
+        // the binary form is unchanged, so hashes and signatures do not depend on the text form
+        assert_eq!(bincode::serialize(&address).unwrap(), address.0.to_vec());
+        assert_eq!(bincode::deserialize::<H160>(&address.0).unwrap(), address);
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, format!("\"{}\"", address));
+        assert_eq!(serde_json::from_str::<H160>(&json).unwrap(), address);
+        assert!(serde_json::from_str::<H160>(&format!("\"{}\"", hex::encode(address.0))).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use ring::constant_time::verify_slices_are_equal;
+
+/// What a client may do through the API. Admins may also do everything read-only clients can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Inspect the chain, state, mempool and network
+    ReadOnly,
+    /// Also change the node: mining, peers, mempool contents and transaction submission
+    Admin,
+}
+
+/// Bearer tokens of the API roles. A role without a token is granted to every client.
+pub struct Auth {
+    admin_token: Option<String>,
+    read_token: Option<String>,
+}
+
+impl Auth {
+    pub fn new(admin_token: Option<String>, read_token: Option<String>) -> Self {
+        Auth {
+            admin_token,
+            read_token,
+        }
+    }
+
+    /// The role of a client presenting `token`, or `None` if it may not use the API at all.
+    pub fn role(&self, token: Option<&str>) -> Option<Role> {
+        let matches = |expected: &Option<String>| match (expected, token) {
+            (None, _) => true,
+            (Some(expected), Some(token)) => {
+                verify_slices_are_equal(expected.as_bytes(), token.as_bytes()).is_ok()
+            }
+            (Some(_), None) => false,
+        };
+        if matches(&self.admin_token) {
+            Some(Role::Admin)
+        } else if matches(&self.read_token) {
+            Some(Role::ReadOnly)
+        } else {
+            None
+        }
+    }
+
+    /// Whether mutating endpoints are open to everyone
+    pub fn admin_is_public(&self) -> bool {
+        self.admin_token.is_none()
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let mut parts = authorization.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_from_tokens() {
+        let auth = Auth::new(Some("admin".to_string()), Some("reader".to_string()));
+        assert_eq!(auth.role(Some("admin")), Some(Role::Admin));
+        assert_eq!(auth.role(Some("reader")), Some(Role::ReadOnly));
+        assert_eq!(auth.role(Some("admi")), None);
+        assert_eq!(auth.role(None), None);
+
+        let auth = Auth::new(Some("admin".to_string()), None);
+        assert_eq!(auth.role(Some("admin")), Some(Role::Admin));
+        assert_eq!(auth.role(Some("wrong")), Some(Role::ReadOnly));
+        assert_eq!(auth.role(None), Some(Role::ReadOnly));
+
+        let auth = Auth::new(None, None);
+        assert!(auth.admin_is_public());
+        assert_eq!(auth.role(None), Some(Role::Admin));
+    }
+
+    #[test]
+    fn bearer_header() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token("abc"), None);
+    }
+}
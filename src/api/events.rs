@@ -0,0 +1,97 @@
+use crate::blockchain::ChainEvent;
+use crate::mempool::MempoolEvent;
+use crossbeam::channel::{self, Receiver};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::time::Duration;
+
+// Server-Sent Events stream of blockchain and mempool events. Every event is sent as an SSE
+// message whose `event` field is the event type and whose `data` field is the event as JSON.
+// Blockchain events are delivered in order, as are mempool events, but the two are interleaved
+// in no particular order.
+
+pub const CHAIN_EVENTS: &[&str] = &["block_accepted", "tip_changed"];
+pub const MEMPOOL_EVENTS: &[&str] = &["transaction_accepted", "transaction_evicted"];
+/// How often a comment is sent on an idle stream, so that closed connections are noticed.
+const KEEPALIVE_INTERVAL_SECS: u64 = 15;
+
+/// Parse the comma-separated `types` filter. All event types are streamed if it is absent.
+pub fn parse_filter(types: Option<&str>) -> Result<HashSet<&'static str>, String> {
+    let known = CHAIN_EVENTS.iter().chain(MEMPOOL_EVENTS.iter());
+    let types = match types {
+        Some(types) => types,
+        None => return Ok(known.cloned().collect()),
+    };
+    let mut filter = HashSet::new();
+    for name in types.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match known.clone().find(|known| **known == name) {
+            Some(known) => filter.insert(*known),
+            None => return Err(format!("unknown event type: {}", name)),
+        };
+    }
+    if filter.is_empty() {
+        return Err("no event types given".to_string());
+    }
+    Ok(filter)
+}
+
+fn format_event(name: &str, data: &str) -> String {
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+/// Write the response header and then the events that pass `filter` until the client goes away.
+/// Pass `channel::never()` for event sources that are filtered out entirely.
+pub fn stream(
+    mut writer: Box<dyn Write + Send>,
+    chain: Receiver<ChainEvent>,
+    mempool: Receiver<MempoolEvent>,
+    filter: HashSet<&'static str>,
+) -> io::Result<()> {
+    writer.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    )?;
+    writer.flush()?;
+    let keepalive = Duration::from_secs(KEEPALIVE_INTERVAL_SECS);
+    loop {
+        let (name, data) = channel::select! {
+            recv(chain) -> event => match event {
+                Ok(event) => (event.name(), serde_json::to_string(&event).unwrap()),
+                Err(_) => return Ok(()),
+            },
+            recv(mempool) -> event => match event {
+                Ok(event) => (event.name(), serde_json::to_string(&event).unwrap()),
+                Err(_) => return Ok(()),
+            },
+            default(keepalive) => {
+                writer.write_all(b": keepalive\n\n")?;
+                writer.flush()?;
+                continue;
+            }
+        };
+        if filter.contains(name) {
+            writer.write_all(format_event(name, &data).as_bytes())?;
+            writer.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_parsing() {
+        assert_eq!(parse_filter(None).unwrap().len(), 4);
+        let filter = parse_filter(Some("tip_changed, transaction_evicted")).unwrap();
+        assert!(filter.contains("tip_changed"));
+        assert!(filter.contains("transaction_evicted"));
+        assert_eq!(filter.len(), 2);
+        assert!(parse_filter(Some("tip_changed,blocks")).is_err());
+        assert!(parse_filter(Some("")).is_err());
+    }
+
+    #[test]
+    fn message_format() {
+        assert_eq!(format_event("tip_changed", "{}"), "event: tip_changed\ndata: {}\n\n");
+    }
+}
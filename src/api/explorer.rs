@@ -0,0 +1,395 @@
+use crate::address::H160;
+use crate::block::{Block, Header};
+use crate::blockchain::{BlockOrigin, Blockchain};
+use crate::crypto::hash::{Hashable, H256};
+use crate::encoding::Encode;
+use crate::mempool::{self, Mempool, TransactionError};
+use crate::transaction::{SignedTransaction as Transaction, Witness};
+use serde::Serialize;
+
+// JSON views of the chain. Hashes are rendered as hex strings and addresses in their checksummed
+// text form.
+
+#[derive(Serialize)]
+pub struct TipView {
+    pub hash: String,
+    pub height: u64,
+    /// The chain transactions must be signed for
+    pub chain_id: String,
+}
+
+#[derive(Serialize)]
+pub struct TransactionView {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: u64,
+    pub nonce: u32,
+    pub chain_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_height: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pub_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigView>,
+}
+
+#[derive(Serialize)]
+pub struct MultisigView {
+    pub threshold: u8,
+    pub pub_keys: Vec<String>,
+    /// Signatures by key index
+    pub signatures: Vec<(u8, String)>,
+}
+
+#[derive(Serialize)]
+pub struct HeaderView {
+    pub parent: String,
+    pub nonce: u32,
+    pub difficulty: String,
+    pub timestamp: u64,
+    pub merkle_root: String,
+}
+
+/// Mirrors `BlockOrigin`; JSON values have no 128-bit integers.
+#[derive(Serialize)]
+pub enum OriginView {
+    Mined,
+    Received { delay_ms: u64 },
+}
+
+#[derive(Serialize)]
+pub struct BlockView {
+    pub hash: String,
+    pub height: u64,
+    pub in_longest_chain: bool,
+    pub origin: Option<OriginView>,
+    pub header: HeaderView,
+    pub transactions: Vec<TransactionView>,
+}
+
+impl TipView {
+    pub fn new(blockchain: &Blockchain) -> Self {
+        TipView {
+            hash: blockchain.tip().to_string(),
+            height: blockchain.length_of_longest_chain(),
+            chain_id: blockchain.chain_id().to_string(),
+        }
+    }
+}
+
+impl From<&Header> for HeaderView {
+    fn from(header: &Header) -> Self {
+        HeaderView {
+            parent: header.parent.to_string(),
+            nonce: header.nonce,
+            difficulty: header.difficulty.to_string(),
+            timestamp: header.timestamp as u64,
+            merkle_root: header.merkle_root.to_string(),
+        }
+    }
+}
+
+impl From<&Transaction> for TransactionView {
+    fn from(tx: &Transaction) -> Self {
+        let mut view = TransactionView {
+            hash: tx.hash().to_string(),
+            from: tx.raw.from_addr.to_string(),
+            to: tx.raw.to_addr.to_string(),
+            value: tx.raw.value,
+            nonce: tx.raw.nonce,
+            chain_id: tx.raw.chain_id.to_string(),
+            expiry_height: tx.raw.expiry_height,
+            pub_key: None,
+            signature: None,
+            multisig: None,
+        };
+        match &tx.witness {
+            Witness::Single { pub_key, signature } => {
+                view.pub_key = Some(hex::encode(pub_key));
+                view.signature = Some(hex::encode(signature));
+            }
+            Witness::Multisig { policy, signatures } => {
+                view.multisig = Some(MultisigView {
+                    threshold: policy.threshold,
+                    pub_keys: policy.pub_keys.iter().map(hex::encode).collect(),
+                    signatures: signatures
+                        .iter()
+                        .map(|signature| (signature.index, hex::encode(&signature.signature)))
+                        .collect(),
+                });
+            }
+        }
+        view
+    }
+}
+
+impl From<&BlockOrigin> for OriginView {
+    fn from(origin: &BlockOrigin) -> Self {
+        match origin {
+            BlockOrigin::Mined => OriginView::Mined,
+            BlockOrigin::Received { delay_ms } => OriginView::Received {
+                delay_ms: *delay_ms as u64,
+            },
+        }
+    }
+}
+
+impl BlockView {
+    /// View of a block in `blockchain`, or `None` if the block is unknown.
+    pub fn new(blockchain: &Blockchain, hash: &H256) -> Option<Self> {
+        let block: &Block = blockchain.hash_to_block.get(hash)?;
+        let height = blockchain.get_length(hash);
+        Some(BlockView {
+            hash: hash.to_string(),
+            height,
+            in_longest_chain: blockchain.hash_at_height(height) == Some(*hash),
+            origin: blockchain.hash_to_origin.get(hash).map(OriginView::from),
+            header: HeaderView::from(&block.header),
+            transactions: block.content.transactions.iter().map(TransactionView::from).collect(),
+        })
+    }
+}
+
+/// Hashes of the longest chain, from the genesis block to the tip.
+pub fn longest_chain(blockchain: &Blockchain) -> Vec<String> {
+    let mut hashes = blockchain.all_blocks_in_longest_chain();
+    hashes.push(blockchain.hash_at_height(0).unwrap());
+    hashes.iter().rev().map(|hash| hash.to_string()).collect()
+}
+
+#[derive(Serialize)]
+pub struct AccountView {
+    pub address: String,
+    pub nonce: u32,
+    pub balance: u64,
+}
+
+/// An account of a wallet key
+#[derive(Serialize)]
+pub struct WalletAccountView {
+    #[serde(flatten)]
+    pub account: AccountView,
+    pub pub_key: String,
+}
+
+#[derive(Serialize)]
+pub struct StateView {
+    pub block: String,
+    pub height: u64,
+    pub state_root: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<Vec<AccountView>>,
+}
+
+impl AccountView {
+    pub fn new(address: &H160, account: Option<&(u32, u64)>) -> Self {
+        // an account that never received coins has nonce 0 and balance 0
+        let (nonce, balance) = account.cloned().unwrap_or((0, 0));
+        AccountView {
+            address: address.to_string(),
+            nonce,
+            balance,
+        }
+    }
+}
+
+impl StateView {
+    /// View of the state after block `hash`, optionally listing all accounts.
+    pub fn new(blockchain: &Blockchain, hash: &H256, with_accounts: bool) -> Option<Self> {
+        let state = blockchain.hash_to_state.get(hash)?;
+        Some(StateView {
+            block: hash.to_string(),
+            height: blockchain.get_length(hash),
+            state_root: state.hash().to_string(),
+            accounts: if with_accounts {
+                Some(
+                    state
+                        .accounts()
+                        .iter()
+                        .map(|(address, account)| AccountView::new(address, Some(account)))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Waiting in the mempool
+    Pending,
+    /// Included in the longest chain
+    Confirmed {
+        block: String,
+        height: u64,
+        confirmations: u64,
+    },
+    /// Only included in blocks off the longest chain
+    Stale { blocks: Vec<String> },
+}
+
+#[derive(Serialize)]
+pub struct TransactionLookupView {
+    #[serde(flatten)]
+    pub status: TransactionStatus,
+    pub transaction: TransactionView,
+}
+
+impl TransactionLookupView {
+    /// Locate a transaction in the blockchain or the mempool, or `None` if it is unknown.
+    pub fn new(blockchain: &Blockchain, mempool: &Mempool, hash: &H256) -> Option<Self> {
+        let blocks = blockchain.transaction_blocks(hash);
+        let confirmed = blockchain.confirming_block(hash).map(|block| (block, blockchain.get_length(&block)));
+        let (status, transaction) = if let Some((block, height)) = confirmed {
+            let status = TransactionStatus::Confirmed {
+                block: block.to_string(),
+                height,
+                confirmations: blockchain.length_of_longest_chain() - height + 1,
+            };
+            (status, blockchain.get_transaction(hash)?)
+        } else if let Some(transaction) = mempool.get_transaction(hash) {
+            (TransactionStatus::Pending, transaction)
+        } else {
+            let status = TransactionStatus::Stale {
+                blocks: blocks.iter().map(|block| block.to_string()).collect(),
+            };
+            (status, blockchain.get_transaction(hash)?)
+        };
+        Some(TransactionLookupView {
+            status,
+            transaction: TransactionView::from(transaction),
+        })
+    }
+}
+
+/// An inclusion proof of a confirmed transaction. `encoded_header` is the canonical encoding of
+/// the header, which hashes to `block`.
+#[derive(Serialize)]
+pub struct ProofView {
+    pub block: String,
+    pub height: u64,
+    pub confirmations: u64,
+    pub header: HeaderView,
+    pub encoded_header: String,
+    pub tx_hash: String,
+    pub index: u32,
+    pub leaf_count: u32,
+    /// Sibling hashes from the leaf up to the root
+    pub path: Vec<String>,
+}
+
+impl ProofView {
+    /// View of the inclusion proof of a transaction, or `None` if it is not confirmed.
+    pub fn new(blockchain: &Blockchain, tx_hash: &H256) -> Option<Self> {
+        let proof = blockchain.inclusion_proof(tx_hash)?;
+        let block = proof.header.hash();
+        let height = blockchain.get_length(&block);
+        Some(ProofView {
+            block: block.to_string(),
+            height,
+            confirmations: blockchain.length_of_longest_chain() - height + 1,
+            header: HeaderView::from(&proof.header),
+            encoded_header: hex::encode(proof.header.encode()),
+            tx_hash: proof.tx_hash.to_string(),
+            index: proof.index,
+            leaf_count: proof.leaf_count,
+            path: proof.path.iter().map(|hash| hash.to_string()).collect(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct MempoolEntryView {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub nonce: u32,
+    pub value: u64,
+    pub bytes: u64,
+    /// Why the transaction cannot be mined on the tip, if it cannot
+    pub error: Option<TransactionError>,
+}
+
+#[derive(Serialize)]
+pub struct MempoolView {
+    pub count: usize,
+    pub valid_count: usize,
+    pub bytes: u64,
+    pub transactions: Vec<MempoolEntryView>,
+}
+
+impl MempoolView {
+    /// View of the mempool, checking every transaction against the tip state.
+    pub fn new(blockchain: &Blockchain, mempool: &Mempool) -> Self {
+        let state = &blockchain.hash_to_state[&blockchain.tip()];
+        let mut transactions: Vec<MempoolEntryView> = mempool
+            .hash_to_transaction
+            .iter()
+            .map(|(hash, tx)| MempoolEntryView {
+                hash: hash.to_string(),
+                from: tx.raw.from_addr.to_string(),
+                to: tx.raw.to_addr.to_string(),
+                nonce: tx.raw.nonce,
+                value: tx.raw.value,
+                bytes: tx.encode().len() as u64,
+                error: mempool::validate(state, tx).err(),
+            })
+            .collect();
+        transactions.sort_by(|a, b| (&a.from, a.nonce, &a.hash).cmp(&(&b.from, b.nonce, &b.hash)));
+        MempoolView {
+            count: transactions.len(),
+            valid_count: transactions.iter().filter(|tx| tx.error.is_none()).count(),
+            bytes: transactions.iter().map(|tx| tx.bytes).sum(),
+            transactions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{default_genesis_seed, ico_keypair};
+    use crate::block::test::generate_block;
+    use crate::transaction::RawTransaction;
+    use ring::signature::KeyPair;
+
+    #[test]
+    fn lookup_skipped_transaction() {
+        let mut blockchain = Blockchain::new();
+        let key = ico_keypair(&default_genesis_seed(), 0);
+        let transfer = |nonce| {
+            let raw = RawTransaction {
+                from_addr: H160::from_pubkey(key.public_key().as_ref()),
+                to_addr: H160::default(),
+                value: 10,
+                nonce,
+                chain_id: blockchain.chain_id(),
+                expiry_height: None,
+            };
+            Transaction::from_raw(raw, &key)
+        };
+        let applied = transfer(1);
+        // the block skips the second transaction, whose nonce is one too high
+        let skipped = transfer(3);
+        let block = generate_block(&blockchain.tip(), vec![applied.clone(), skipped.clone()]);
+        blockchain.insert(&block);
+        let mempool = Mempool::new();
+
+        let view = TransactionLookupView::new(&blockchain, &mempool, &applied.hash()).unwrap();
+        match view.status {
+            TransactionStatus::Confirmed { block: hash, height, confirmations } => {
+                assert_eq!(hash, block.hash().to_string());
+                assert_eq!((height, confirmations), (1, 1));
+            }
+            _ => panic!("the applied transaction should be confirmed"),
+        }
+        assert!(TransactionLookupView::new(&blockchain, &mempool, &skipped.hash()).is_none());
+        assert!(ProofView::new(&blockchain, &skipped.hash()).is_none());
+    }
+}
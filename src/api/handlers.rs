@@ -0,0 +1,574 @@
+use super::explorer::{
+    self, AccountView, BlockView, MempoolView, ProofView, StateView, TipView, TransactionLookupView,
+    WalletAccountView,
+};
+use super::auth::Role;
+use super::rpc;
+use super::Node;
+use crate::address::H160;
+use crate::blockchain::Blockchain;
+use crate::crypto::hash::{Hashable, H256};
+use crate::encoding::Decode;
+use crate::crypto::hd::DerivationPath;
+use crate::mempool::{EvictionReason, TransactionError};
+use crate::network::server::PeerInfo;
+use crate::transaction::{MultisigPolicy, SignedTransaction as Transaction, Witness};
+use crate::wallet::{self, WalletError};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Named parameters of an API call. REST routes pass their query string as string values, while
+/// JSON-RPC calls may also use numbers and objects.
+pub type Params = serde_json::Map<String, Value>;
+
+/// A handler returns the result of a call, or `Value::Null` if there is nothing to return.
+pub type Handler = fn(&Node, &Params) -> Result<Value, Error>;
+
+/// Reasons an API call can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// A parameter is missing or malformed
+    InvalidParams(String),
+    /// The requested block, transaction or peer does not exist
+    NotFound(String),
+    /// The submitted transaction was rejected
+    Rejected(TransactionError),
+    /// The operation was attempted but failed
+    Failed(String),
+    /// The client's role does not allow the call
+    Unauthorized(String),
+}
+
+impl Error {
+    /// The JSON-RPC error code. Errors other than bad parameters use the range reserved for
+    /// implementation-defined server errors.
+    pub fn code(&self) -> i64 {
+        match self {
+            Error::InvalidParams(_) => rpc::INVALID_PARAMS,
+            Error::NotFound(_) => -32000,
+            Error::Rejected(_) => -32001,
+            Error::Failed(_) => -32002,
+            Error::Unauthorized(_) => -32003,
+        }
+    }
+
+    /// Structured details of the error, if any.
+    pub fn data(&self) -> Option<Value> {
+        match self {
+            Error::Rejected(e) => Some(serde_json::to_value(e).unwrap()),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidParams(msg)
+            | Error::NotFound(msg)
+            | Error::Failed(msg)
+            | Error::Unauthorized(msg) => write!(f, "{}", msg),
+            Error::Rejected(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The API methods by name, shared by the REST routes and the JSON-RPC endpoint.
+pub struct Registry {
+    handlers: HashMap<&'static str, (Role, Handler)>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry {
+            handlers: HashMap::new(),
+        };
+        registry.register("chain.tip", Role::ReadOnly, chain_tip);
+        registry.register("chain.longest_chain", Role::ReadOnly, chain_longest_chain);
+        registry.register("chain.block", Role::ReadOnly, chain_block);
+        registry.register("chain.proof", Role::ReadOnly, chain_proof);
+        registry.register("state.account", Role::ReadOnly, state_account);
+        registry.register("state.accounts", Role::ReadOnly, state_accounts);
+        registry.register("state.root", Role::ReadOnly, state_root);
+        registry.register("mempool.list", Role::ReadOnly, mempool_list);
+        registry.register("mempool.drop", Role::Admin, mempool_drop);
+        registry.register("tx.submit", Role::Admin, tx_submit);
+        registry.register("tx.get", Role::ReadOnly, tx_get);
+        registry.register("miner.start", Role::Admin, miner_start);
+        registry.register("miner.exit", Role::Admin, miner_exit);
+        registry.register("network.ping", Role::Admin, network_ping);
+        registry.register("network.latency", Role::ReadOnly, network_latency);
+        registry.register("network.peers", Role::ReadOnly, network_peers);
+        registry.register("network.connect", Role::Admin, network_connect);
+        registry.register("network.disconnect", Role::Admin, network_disconnect);
+        registry.register("wallet.list", Role::Admin, wallet_list);
+        registry.register("wallet.generate", Role::Admin, wallet_generate);
+        registry.register("wallet.import", Role::Admin, wallet_import);
+        registry.register("wallet.export", Role::Admin, wallet_export);
+        registry.register("wallet.send", Role::Admin, wallet_send);
+        registry.register("wallet.send_multisig", Role::Admin, wallet_send_multisig);
+        registry.register("multisig.address", Role::ReadOnly, multisig_address);
+        registry
+    }
+
+    /// Register a method that clients with at least role `role` may call.
+    pub fn register(&mut self, method: &'static str, role: Role, handler: Handler) {
+        self.handlers.insert(method, (role, handler));
+    }
+
+    /// Call a method on behalf of a client with role `role`, or return `None` if no such method
+    /// is registered.
+    pub fn call(&self, node: &Node, method: &str, params: &Params, role: Role) -> Option<Result<Value, Error>> {
+        let (required, handler) = self.handlers.get(method)?;
+        if role < *required {
+            return Some(Err(Error::Unauthorized(format!("{} requires the admin role", method))));
+        }
+        Some(handler(node, params))
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, Error> {
+    Ok(serde_json::to_value(value).unwrap())
+}
+
+/// Parse an optional parameter given either as a string or as a JSON number.
+fn param<T>(params: &Params, name: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let raw = match params.get(name) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(_) => return Err(Error::InvalidParams(format!("error parsing {}: expected a string", name))),
+    };
+    raw.parse::<T>()
+        .map(Some)
+        .map_err(|e| Error::InvalidParams(format!("error parsing {}: {}", name, e)))
+}
+
+fn required<T>(params: &Params, name: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    param(params, name)?.ok_or_else(|| Error::InvalidParams(format!("missing {}", name)))
+}
+
+/// The block named by the optional `block` parameter, defaulting to the tip.
+fn block_param(params: &Params, blockchain: &Blockchain) -> Result<H256, Error> {
+    match param::<H256>(params, "block")? {
+        Some(hash) if !blockchain.contains_block(&hash) => Err(Error::NotFound("block not found".to_string())),
+        Some(hash) => Ok(hash),
+        None => Ok(blockchain.tip()),
+    }
+}
+
+/// Decode a transaction given either as JSON or as its hex-encoded canonical encoding.
+fn decode_transaction(body: &str) -> Result<Transaction, String> {
+    let body = body.trim();
+    if body.starts_with('{') {
+        serde_json::from_str(body).map_err(|e| format!("error parsing JSON transaction: {}", e))
+    } else {
+        let bytes = hex::decode(body).map_err(|e| format!("error parsing hex transaction: {}", e))?;
+        Transaction::decode(&bytes).map_err(|e| format!("error decoding transaction: {}", e))
+    }
+}
+
+/// The optional `transaction` parameter, given either as a string or as a JSON object.
+fn transaction_param(params: &Params) -> Result<Option<Transaction>, Error> {
+    match params.get("transaction") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => decode_transaction(s).map(Some).map_err(Error::InvalidParams),
+        Some(value @ Value::Object(_)) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(|e| Error::InvalidParams(format!("error parsing JSON transaction: {}", e))),
+        Some(_) => Err(Error::InvalidParams("error parsing transaction: expected a string or an object".to_string())),
+    }
+}
+
+/// The multisig policy given by `threshold` and `pub_keys`, a comma-separated string or an array
+/// of hex-encoded public keys.
+fn policy_param(params: &Params) -> Result<MultisigPolicy, Error> {
+    let threshold: u8 = required(params, "threshold")?;
+    let pub_keys: Vec<String> = match params.get("pub_keys") {
+        Some(Value::String(s)) => s.split(',').map(|key| key.trim().to_string()).collect(),
+        Some(Value::Array(keys)) => keys
+            .iter()
+            .map(|key| key.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or_else(|| Error::InvalidParams("error parsing pub_keys: expected strings".to_string()))?,
+        Some(_) => return Err(Error::InvalidParams("error parsing pub_keys: expected a string or an array".to_string())),
+        None => return Err(Error::InvalidParams("missing pub_keys".to_string())),
+    };
+    let pub_keys = pub_keys
+        .iter()
+        .map(hex::decode)
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::InvalidParams(format!("error parsing pub_keys: {}", e)))?;
+    MultisigPolicy::new(threshold, pub_keys).map_err(Error::InvalidParams)
+}
+
+fn chain_tip(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    to_value(TipView::new(&blockchain))
+}
+
+fn chain_longest_chain(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    to_value(explorer::longest_chain(&blockchain))
+}
+
+fn chain_block(node: &Node, params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let hash = if let Some(hash) = param::<H256>(params, "hash")? {
+        hash
+    } else if let Some(height) = param::<u64>(params, "height")? {
+        blockchain
+            .hash_at_height(height)
+            .ok_or_else(|| Error::NotFound("height beyond the tip".to_string()))?
+    } else {
+        return Err(Error::InvalidParams("missing hash or height".to_string()));
+    };
+    match BlockView::new(&blockchain, &hash) {
+        Some(view) => to_value(view),
+        None => Err(Error::NotFound("block not found".to_string())),
+    }
+}
+
+/// The Merkle proof of the transaction `hash` in its block on the longest chain, with the block
+/// header, so that clients following only headers can check it.
+fn chain_proof(node: &Node, params: &Params) -> Result<Value, Error> {
+    let hash: H256 = required(params, "hash")?;
+    let blockchain = node.blockchain.lock().unwrap();
+    match ProofView::new(&blockchain, &hash) {
+        Some(view) => to_value(view),
+        None => Err(Error::NotFound("transaction not confirmed on the longest chain".to_string())),
+    }
+}
+
+fn state_account(node: &Node, params: &Params) -> Result<Value, Error> {
+    let address: H160 = required(params, "address")?;
+    let blockchain = node.blockchain.lock().unwrap();
+    let block = block_param(params, &blockchain)?;
+    let state = &blockchain.hash_to_state[&block];
+    to_value(AccountView::new(&address, state.get(&address)))
+}
+
+fn state_accounts(node: &Node, params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let block = block_param(params, &blockchain)?;
+    to_value(StateView::new(&blockchain, &block, true).unwrap())
+}
+
+fn state_root(node: &Node, params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let block = block_param(params, &blockchain)?;
+    to_value(StateView::new(&blockchain, &block, false).unwrap())
+}
+
+fn multisig_address(_node: &Node, params: &Params) -> Result<Value, Error> {
+    let policy = policy_param(params)?;
+    Ok(serde_json::json!({ "address": policy.address().to_string() }))
+}
+
+fn mempool_list(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let mempool = node.mempool.lock().unwrap();
+    to_value(MempoolView::new(&blockchain, &mempool))
+}
+
+fn mempool_drop(node: &Node, params: &Params) -> Result<Value, Error> {
+    let hash: H256 = required(params, "hash")?;
+    match node.mempool.lock().unwrap().remove(&hash, EvictionReason::Dropped) {
+        Some(_) => Ok(Value::Null),
+        None => Err(Error::NotFound("transaction not in mempool".to_string())),
+    }
+}
+
+/// Validate a transaction against the tip state and the pending transactions of its sender, insert
+/// it into the mempool like the worker does for transactions received from peers, and announce it.
+fn tx_submit(node: &Node, params: &Params) -> Result<Value, Error> {
+    let transaction = transaction_param(params)?.ok_or_else(|| Error::InvalidParams("missing transaction".to_string()))?;
+    let blockchain = node.blockchain.lock().unwrap();
+    submit(node, &blockchain, transaction)
+}
+
+/// Insert a transaction that is valid after the pending transactions of its sender into the mempool
+/// and announce it. The caller holds the blockchain lock.
+fn submit(node: &Node, blockchain: &Blockchain, transaction: Transaction) -> Result<Value, Error> {
+    let hash = transaction.hash();
+    {
+        let mut mempool = node.mempool.lock().unwrap();
+        if mempool.contains_transaction(&hash) {
+            return Err(Error::Rejected(TransactionError::AlreadyKnown));
+        }
+        mempool
+            .validate_pending(&blockchain.hash_to_state[&blockchain.tip()], &transaction)
+            .map_err(Error::Rejected)?;
+        mempool.insert(transaction);
+    }
+    node.inventory.announce_transactions(vec![hash]);
+    Ok(serde_json::json!({ "hash": hash.to_string() }))
+}
+
+fn tx_get(node: &Node, params: &Params) -> Result<Value, Error> {
+    let hash: H256 = required(params, "hash")?;
+    let blockchain = node.blockchain.lock().unwrap();
+    let mempool = node.mempool.lock().unwrap();
+    match TransactionLookupView::new(&blockchain, &mempool, &hash) {
+        Some(view) => to_value(view),
+        None => Err(Error::NotFound("transaction not found".to_string())),
+    }
+}
+
+fn miner_start(node: &Node, params: &Params) -> Result<Value, Error> {
+    let lambda: u64 = required(params, "lambda")?;
+    node.miner.start(lambda);
+    Ok(Value::Null)
+}
+
+fn miner_exit(node: &Node, _params: &Params) -> Result<Value, Error> {
+    node.miner.exit();
+    Ok(Value::Null)
+}
+
+fn network_ping(node: &Node, _params: &Params) -> Result<Value, Error> {
+    node.keepalive.ping_now();
+    Ok(Value::Null)
+}
+
+fn network_latency(node: &Node, _params: &Params) -> Result<Value, Error> {
+    to_value(node.keepalive.stats())
+}
+
+/// A connected peer, and whether the connector redials it when the connection drops.
+#[derive(Serialize)]
+struct PeerView {
+    #[serde(flatten)]
+    info: PeerInfo,
+    #[serde(rename = "static")]
+    is_static: bool,
+}
+
+fn network_peers(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let static_peers = node.connector.peers();
+    let peers: Vec<PeerView> = node
+        .network
+        .peers()
+        .into_iter()
+        .map(|info| PeerView {
+            info,
+            is_static: static_peers.contains(&info.addr),
+        })
+        .collect();
+    to_value(peers)
+}
+
+fn network_connect(node: &Node, params: &Params) -> Result<Value, Error> {
+    let addr: SocketAddr = required(params, "addr")?;
+    // the connector dials right away, and keeps redialing the peer whenever it drops
+    node.connector.add_peer(addr);
+    Ok(Value::Null)
+}
+
+fn network_disconnect(node: &Node, params: &Params) -> Result<Value, Error> {
+    let addr: SocketAddr = required(params, "addr")?;
+    // forget static peers first so that they are not redialed
+    node.connector.remove_peer(addr);
+    if node.network.disconnect(addr) {
+        Ok(Value::Null)
+    } else {
+        Err(Error::NotFound("peer not connected".to_string()))
+    }
+}
+
+fn wallet_error(e: WalletError) -> Error {
+    match e {
+        WalletError::UnknownAddress(_) | WalletError::NotACosigner(_) => Error::NotFound(e.to_string()),
+        WalletError::InvalidSeed(_) | WalletError::InsufficientBalance { .. } => Error::InvalidParams(e.to_string()),
+        _ => Error::Failed(e.to_string()),
+    }
+}
+
+/// The wallet's addresses with their accounts at the tip.
+fn wallet_list(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let state = &blockchain.hash_to_state[&blockchain.tip()];
+    let wallet = node.wallet.lock().unwrap();
+    let accounts: Vec<WalletAccountView> = wallet
+        .addresses()
+        .iter()
+        .map(|address| WalletAccountView {
+            account: AccountView::new(address, state.get(address)),
+            pub_key: hex::encode(wallet.public_key(address).unwrap()),
+        })
+        .collect();
+    to_value(accounts)
+}
+
+fn wallet_generate(node: &Node, _params: &Params) -> Result<Value, Error> {
+    let address = node.wallet.lock().unwrap().generate().map_err(wallet_error)?;
+    Ok(serde_json::json!({ "address": address.to_string() }))
+}
+
+/// Import a key given either as a hex `seed`, or as a seed `phrase` with an optional `passphrase`
+/// and the derivation `path` of the key.
+fn wallet_import(node: &Node, params: &Params) -> Result<Value, Error> {
+    let address = if let Some(phrase) = param::<String>(params, "phrase")? {
+        let path: DerivationPath = required(params, "path")?;
+        let passphrase = param::<String>(params, "passphrase")?.unwrap_or_default();
+        node.wallet.lock().unwrap().import_derived(&phrase, &passphrase, &path)
+    } else {
+        let seed: String = required(params, "seed")?;
+        let seed = wallet::parse_seed(&seed).map_err(wallet_error)?;
+        node.wallet.lock().unwrap().import(seed)
+    }
+    .map_err(wallet_error)?;
+    Ok(serde_json::json!({ "address": address.to_string() }))
+}
+
+fn wallet_export(node: &Node, params: &Params) -> Result<Value, Error> {
+    let address: H160 = required(params, "address")?;
+    let seed = node.wallet.lock().unwrap().export(&address).map_err(wallet_error)?;
+    Ok(serde_json::json!({ "address": address.to_string(), "seed": hex::encode(seed) }))
+}
+
+/// Build a transfer with the next nonce of `from` after its pending transactions, sign it with the
+/// wallet key and submit it like `tx.submit`. An optional `expiry_height` bounds the height it can
+/// be mined at.
+fn wallet_send(node: &Node, params: &Params) -> Result<Value, Error> {
+    let from: H160 = required(params, "from")?;
+    let to: H160 = required(params, "to")?;
+    let value: u64 = required(params, "value")?;
+    let expiry_height: Option<u64> = param(params, "expiry_height")?;
+    let blockchain = node.blockchain.lock().unwrap();
+    let transaction = node
+        .wallet
+        .lock()
+        .unwrap()
+        .build_transaction(
+            &blockchain.hash_to_state[&blockchain.tip()],
+            &node.mempool.lock().unwrap(),
+            &from,
+            &to,
+            value,
+            expiry_height,
+        )
+        .map_err(wallet_error)?;
+    submit(node, &blockchain, transaction)
+}
+
+/// Co-sign a new or given multisig transfer and submit it once it has `threshold` signatures.
+fn wallet_send_multisig(node: &Node, params: &Params) -> Result<Value, Error> {
+    let blockchain = node.blockchain.lock().unwrap();
+    let transaction = {
+        let wallet = node.wallet.lock().unwrap();
+        match transaction_param(params)? {
+            Some(mut transaction) => {
+                wallet.sign_multisig(&mut transaction).map_err(wallet_error)?;
+                transaction
+            }
+            None => {
+                let to: H160 = required(params, "to")?;
+                let value: u64 = required(params, "value")?;
+                let expiry_height: Option<u64> = param(params, "expiry_height")?;
+                let state = &blockchain.hash_to_state[&blockchain.tip()];
+                wallet
+                    .build_multisig_transaction(
+                        state,
+                        &node.mempool.lock().unwrap(),
+                        policy_param(params)?,
+                        &to,
+                        value,
+                        expiry_height,
+                    )
+                    .map_err(wallet_error)?
+            }
+        }
+    };
+    if transaction.verify_signature() {
+        return submit(node, &blockchain, transaction);
+    }
+    let (signatures, threshold) = match &transaction.witness {
+        Witness::Multisig { policy, signatures } => (signatures.len(), policy.threshold),
+        Witness::Single { .. } => return Err(Error::Rejected(TransactionError::InvalidSignature)),
+    };
+    Ok(serde_json::json!({
+        "transaction": transaction,
+        "signatures": signatures,
+        "threshold": threshold,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::Mempool;
+    use crate::network::{connector, inventory, keepalive, server};
+    use crate::wallet::Wallet;
+    use crossbeam::channel;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    fn test_node() -> Node {
+        let (msg_tx, _msg_rx) = channel::unbounded();
+        let (server_ctx, server) = server::new("127.0.0.1:0".parse().unwrap(), msg_tx).unwrap();
+        server_ctx.start().unwrap();
+        let (_, keepalive) = keepalive::new(&server);
+        let (_, inventory) = inventory::new(&server);
+        let (connector_ctx, connector) = connector::new(&server);
+        connector_ctx.start();
+        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let (_, miner) = crate::miner::new(&inventory, &blockchain, &mempool);
+        Node {
+            miner,
+            network: server,
+            connector,
+            keepalive,
+            inventory,
+            blockchain,
+            mempool,
+            wallet: Arc::new(Mutex::new(Wallet::new())),
+        }
+    }
+
+    #[test]
+    fn connect_adds_static_peer() {
+        let node = test_node();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut params = Params::new();
+        params.insert("addr".to_string(), Value::String(addr.to_string()));
+        network_connect(&node, &params).unwrap();
+        assert_eq!(node.connector.peers(), vec![addr]);
+
+        // the connector dials the peer, which is then listed as static
+        let mut peers = Value::Array(vec![]);
+        for _ in 0..100 {
+            peers = network_peers(&node, &Params::new()).unwrap();
+            if peers.as_array().is_some_and(|p| !p.is_empty()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(peers[0]["addr"], addr.to_string());
+        assert_eq!(peers[0]["static"], true);
+
+        network_disconnect(&node, &params).unwrap();
+        assert!(node.connector.peers().is_empty());
+    }
+}
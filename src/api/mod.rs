@@ -1,34 +1,98 @@
+pub mod auth;
+mod events;
+mod explorer;
+mod handlers;
+mod rpc;
+
 use serde::Serialize;
+use crate::blockchain::Blockchain;
+use crate::mempool::Mempool;
+use crate::metrics::metrics;
 use crate::miner::Handle as MinerHandle;
+use crate::network::connector::Handle as ConnectorHandle;
+use crate::network::inventory::Handle as InventoryHandle;
+use crate::network::keepalive::Handle as KeepaliveHandle;
 use crate::network::server::Handle as NetworkServerHandle;
-use crate::network::message::Message;
+use crate::wallet::Wallet;
+use auth::{Auth, Role};
+use handlers::{Error, Params, Registry};
 
-use log::info;
-use std::collections::HashMap;
+use crossbeam::channel;
+use log::{debug, info};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use tiny_http::Header;
+use tiny_http::Method;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
 use url::Url;
 
+/// Handles to the components of the node that the API exposes.
+#[derive(Clone)]
+pub struct Node {
+    pub miner: MinerHandle,
+    pub network: NetworkServerHandle,
+    pub connector: ConnectorHandle,
+    pub keepalive: KeepaliveHandle,
+    pub inventory: InventoryHandle,
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub wallet: Arc<Mutex<Wallet>>,
+}
+
 pub struct Server {
     handle: HTTPServer,
-    miner: MinerHandle,
-    network: NetworkServerHandle,
+    node: Node,
+    registry: Arc<Registry>,
+    auth: Arc<Auth>,
+    /// The highest role granted on this listener
+    max_role: Role,
 }
 
 #[derive(Serialize)]
 struct ApiResponse {
     success: bool,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Value>,
 }
 
+/// REST routes and the registry methods they call. Query parameters are passed to the method.
+const ROUTES: &[(&str, &str)] = &[
+    ("/miner/start", "miner.start"),
+    ("/miner/exit", "miner.exit"),
+    ("/network/ping", "network.ping"),
+    ("/network/latency", "network.latency"),
+    ("/network/peers", "network.peers"),
+    ("/network/connect", "network.connect"),
+    ("/network/disconnect", "network.disconnect"),
+    ("/blockchain/tip", "chain.tip"),
+    ("/blockchain/longest-chain", "chain.longest_chain"),
+    ("/blockchain/block", "chain.block"),
+    ("/blockchain/proof", "chain.proof"),
+    ("/state/account", "state.account"),
+    ("/state/accounts", "state.accounts"),
+    ("/state/root", "state.root"),
+    ("/mempool", "mempool.list"),
+    ("/mempool/drop", "mempool.drop"),
+    ("/wallet", "wallet.list"),
+    ("/wallet/generate", "wallet.generate"),
+    ("/wallet/import", "wallet.import"),
+    ("/wallet/export", "wallet.export"),
+    ("/wallet/send", "wallet.send"),
+    ("/wallet/send-multisig", "wallet.send_multisig"),
+    ("/multisig/address", "multisig.address"),
+];
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
         let payload = ApiResponse {
             success: $success,
             message: $message.to_string(),
+            error: None,
         };
         let resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
             .with_header(content_type);
@@ -36,23 +100,65 @@ macro_rules! respond_result {
     }};
 }
 
+macro_rules! respond_error {
+    ( $req:expr, $error:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let payload = ApiResponse {
+            success: false,
+            message: $error.to_string(),
+            error: $error.data(),
+        };
+        let mut resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
+            .with_header(content_type);
+        if let Error::Unauthorized(_) = $error {
+            let challenge = "WWW-Authenticate: Bearer".parse::<Header>().unwrap();
+            resp = resp.with_status_code(401).with_header(challenge);
+        }
+        $req.respond(resp).unwrap();
+    }};
+}
+
+macro_rules! respond_json {
+    ( $req:expr, $payload:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let resp = Response::from_string(serde_json::to_string_pretty(&$payload).unwrap())
+            .with_header(content_type);
+        $req.respond(resp).unwrap();
+    }};
+}
+
 impl Server {
-    pub fn start(
-        addr: std::net::SocketAddr,
-        miner: &MinerHandle,
-        network: &NetworkServerHandle,
-    ) {
-        let handle = HTTPServer::http(&addr).unwrap();
+    /// Serve the API at `addr`. Clients get the role of the token they present, but at most
+    /// `max_role`, so that a listener with `Role::ReadOnly` only serves read-only endpoints.
+    pub fn start(addr: SocketAddr, node: Node, auth: &Arc<Auth>, max_role: Role) {
+        let handle = HTTPServer::http(addr).unwrap();
         let server = Self {
             handle,
-            miner: miner.clone(),
-            network: network.clone(),
+            node,
+            registry: Arc::new(Registry::new()),
+            auth: Arc::clone(auth),
+            max_role,
         };
         thread::spawn(move || {
-            for req in server.handle.incoming_requests() {
-                let miner = server.miner.clone();
-                let network = server.network.clone();
+            for mut req in server.handle.incoming_requests() {
+                let node = server.node.clone();
+                let registry = Arc::clone(&server.registry);
+                let auth = Arc::clone(&server.auth);
+                let max_role = server.max_role;
                 thread::spawn(move || {
+                    let token = req
+                        .headers()
+                        .iter()
+                        .find(|header| header.field.equiv("Authorization"))
+                        .and_then(|header| auth::bearer_token(header.value.as_str()));
+                    let role = match auth.role(token) {
+                        Some(role) => role.min(max_role),
+                        None => {
+                            let e = Error::Unauthorized("missing or invalid API token".to_string());
+                            respond_error!(req, e);
+                            return;
+                        }
+                    };
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
                     let url = match base_url.join(req.url()) {
@@ -62,57 +168,99 @@ impl Server {
                             return;
                         }
                     };
-                    match url.path() {
-                        "/miner/start" => {
-                            let params = url.query_pairs();
-                            let params: HashMap<_, _> = params.into_owned().collect();
-                            let lambda = match params.get("lambda") {
-                                Some(v) => v,
-                                None => {
-                                    respond_result!(req, false, "missing lambda");
-                                    return;
-                                }
-                            };
-                            let lambda = match lambda.parse::<u64>() {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    respond_result!(
-                                        req,
-                                        false,
-                                        format!("error parsing lambda: {}", e)
-                                    );
-                                    return;
-                                }
-                            };
-                            miner.start(lambda);
-                            respond_result!(req, true, "ok");
+                    let mut params: Params = url
+                        .query_pairs()
+                        .into_owned()
+                        .map(|(name, value)| (name, Value::String(value)))
+                        .collect();
+                    let path = url.path();
+                    if path == "/metrics" {
+                        let content_type = "Content-Type: text/plain; version=0.0.4"
+                            .parse::<Header>()
+                            .unwrap();
+                        let resp = Response::from_data(metrics().render()).with_header(content_type);
+                        req.respond(resp).unwrap();
+                        return;
+                    }
+                    if path == "/events" {
+                        let types = params.get("types").and_then(Value::as_str);
+                        let filter = match events::parse_filter(types) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                respond_result!(req, false, e);
+                                return;
+                            }
+                        };
+                        let wants = |names: &[&str]| names.iter().any(|name| filter.contains(name));
+                        let chain = if wants(events::CHAIN_EVENTS) {
+                            node.blockchain.lock().unwrap().subscribe()
+                        } else {
+                            channel::never()
+                        };
+                        let mempool = if wants(events::MEMPOOL_EVENTS) {
+                            node.mempool.lock().unwrap().subscribe()
+                        } else {
+                            channel::never()
+                        };
+                        if let Err(e) = events::stream(req.into_writer(), chain, mempool, filter) {
+                            debug!("Event stream closed: {}", e);
                         }
-                        "/miner/exit" => {
-                            miner.exit();
-                            respond_result!(req, true, "ok");
+                        return;
+                    }
+                    let method = if path == "/rpc" || path == "/tx/submit" {
+                        if *req.method() != Method::Post {
+                            respond_result!(req, false, format!("use POST for {}", path));
+                            return;
                         }
-                        "/network/ping" => {
-                            network.broadcast(Message::Ping(String::from("Test ping")));
-                            respond_result!(req, true, "ok");
+                        let mut body = String::new();
+                        if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                            respond_result!(req, false, format!("error reading body: {}", e));
+                            return;
                         }
-                        _ => {
-                            let content_type =
-                                "Content-Type: application/json".parse::<Header>().unwrap();
-                            let payload = ApiResponse {
-                                success: false,
-                                message: "endpoint not found".to_string(),
-                            };
-                            let resp = Response::from_string(
-                                serde_json::to_string_pretty(&payload).unwrap(),
-                            )
-                            .with_header(content_type)
-                            .with_status_code(404);
-                            req.respond(resp).unwrap();
+                        if path == "/rpc" {
+                            let response = rpc::handle(&body, |method, params| {
+                                registry.call(&node, method, params, role)
+                            });
+                            match response {
+                                Some(response) => respond_json!(req, response),
+                                None => req.respond(Response::empty(204)).unwrap(),
+                            }
+                            return;
                         }
+                        params.insert("transaction".to_string(), Value::String(body));
+                        "tx.submit"
+                    } else if let Some(hash) = path.strip_prefix("/tx/") {
+                        params.insert("hash".to_string(), Value::String(hash.to_string()));
+                        "tx.get"
+                    } else if let Some((_, method)) = ROUTES.iter().find(|(route, _)| *route == path) {
+                        method
+                    } else {
+                        let content_type =
+                            "Content-Type: application/json".parse::<Header>().unwrap();
+                        let payload = ApiResponse {
+                            success: false,
+                            message: "endpoint not found".to_string(),
+                            error: None,
+                        };
+                        let resp = Response::from_string(
+                            serde_json::to_string_pretty(&payload).unwrap(),
+                        )
+                        .with_header(content_type)
+                        .with_status_code(404);
+                        req.respond(resp).unwrap();
+                        return;
+                    };
+                    match registry.call(&node, method, &params, role).unwrap() {
+                        Ok(Value::Null) => respond_result!(req, true, "ok"),
+                        Ok(result) => respond_json!(req, result),
+                        Err(e) => respond_error!(req, e),
                     }
                 });
             }
         });
-        info!("API server listening at {}", &addr);
+        match max_role {
+            Role::Admin => info!("API server listening at {}", &addr),
+            Role::ReadOnly => info!("Read-only API server listening at {}", &addr),
+        }
     }
 }
@@ -0,0 +1,154 @@
+use super::handlers::{Error, Params};
+use serde_json::{json, Value};
+
+// JSON-RPC 2.0 over HTTP. Requests name a method of the handler registry and pass its parameters
+// by name; both single calls and batches are accepted.
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+fn success(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "result": result, "id": id })
+}
+
+fn failure(id: Value, code: i64, message: String, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+/// Handle the body of a JSON-RPC request, calling `dispatch` for every method call. `dispatch`
+/// returns `None` for unknown methods. Returns `None` if there is nothing to send back, i.e. the
+/// request only contained notifications.
+pub fn handle<F>(body: &str, dispatch: F) -> Option<Value>
+where
+    F: Fn(&str, &Params) -> Option<Result<Value, Error>>,
+{
+    let request: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => return Some(failure(Value::Null, PARSE_ERROR, format!("parse error: {}", e), None)),
+    };
+    match request {
+        Value::Array(batch) => {
+            if batch.is_empty() {
+                let message = "invalid request: empty batch".to_string();
+                return Some(failure(Value::Null, INVALID_REQUEST, message, None));
+            }
+            let responses: Vec<Value> = batch
+                .into_iter()
+                .filter_map(|request| handle_call(request, &dispatch))
+                .collect();
+            if responses.is_empty() {
+                None
+            } else {
+                Some(Value::Array(responses))
+            }
+        }
+        request => handle_call(request, &dispatch),
+    }
+}
+
+/// Handle a single call. Notifications, i.e. calls without an `id`, get no response.
+fn handle_call<F>(request: Value, dispatch: &F) -> Option<Value>
+where
+    F: Fn(&str, &Params) -> Option<Result<Value, Error>>,
+{
+    let invalid = |message: &str| failure(Value::Null, INVALID_REQUEST, format!("invalid request: {}", message), None);
+    let mut request = match request {
+        Value::Object(request) => request,
+        _ => return Some(invalid("expected an object")),
+    };
+    let id = request.remove("id");
+    match id {
+        None | Some(Value::Null) | Some(Value::String(_)) | Some(Value::Number(_)) => {}
+        Some(_) => return Some(invalid("id must be a string, a number or null")),
+    }
+    if request.get("jsonrpc") != Some(&Value::String("2.0".to_string())) {
+        return Some(invalid("jsonrpc must be \"2.0\""));
+    }
+    let method = match request.remove("method") {
+        Some(Value::String(method)) => method,
+        _ => return Some(invalid("method must be a string")),
+    };
+    let result = match request.remove("params") {
+        None | Some(Value::Null) => dispatch(&method, &Params::new()),
+        Some(Value::Object(params)) => dispatch(&method, &params),
+        Some(Value::Array(ref params)) if params.is_empty() => dispatch(&method, &Params::new()),
+        Some(_) => {
+            let message = "params must be passed by name".to_string();
+            return id.map(|id| failure(id, INVALID_PARAMS, message, None));
+        }
+    };
+    let id = id?;
+    Some(match result {
+        None => failure(id, METHOD_NOT_FOUND, format!("method not found: {}", method), None),
+        Some(Ok(result)) => success(id, result),
+        Some(Err(e)) => failure(id, e.code(), e.to_string(), e.data()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::TransactionError;
+
+    fn dispatch(method: &str, params: &Params) -> Option<Result<Value, Error>> {
+        match method {
+            "echo" => Some(Ok(Value::Object(params.clone()))),
+            "nothing" => Some(Ok(Value::Null)),
+            "reject" => Some(Err(Error::Rejected(TransactionError::InvalidSignature))),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn single_calls() {
+        let response = handle(r#"{"jsonrpc":"2.0","method":"echo","params":{"a":1},"id":7}"#, dispatch).unwrap();
+        assert_eq!(response, json!({"jsonrpc": "2.0", "result": {"a": 1}, "id": 7}));
+        let response = handle(r#"{"jsonrpc":"2.0","method":"nothing","id":"x"}"#, dispatch).unwrap();
+        assert_eq!(response, json!({"jsonrpc": "2.0", "result": null, "id": "x"}));
+        let response = handle(r#"{"jsonrpc":"2.0","method":"reject","id":1}"#, dispatch).unwrap();
+        assert_eq!(response["error"]["code"], -32001);
+        assert_eq!(response["error"]["data"], json!({"reason": "invalid_signature"}));
+        let response = handle(r#"{"jsonrpc":"2.0","method":"nope","id":1}"#, dispatch).unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        let response = handle(r#"{"jsonrpc":"2.0","method":"echo","params":[1],"id":1}"#, dispatch).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let response = handle("{", dispatch).unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+        let response = handle("[]", dispatch).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        let response = handle(r#"{"method":"echo","id":1}"#, dispatch).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        let response = handle(r#"{"jsonrpc":"2.0","method":"echo","id":[]}"#, dispatch).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn batches_and_notifications() {
+        assert!(handle(r#"{"jsonrpc":"2.0","method":"nothing"}"#, dispatch).is_none());
+        assert!(handle(r#"{"jsonrpc":"2.0","method":"nope"}"#, dispatch).is_none());
+        let body = r#"[
+            {"jsonrpc":"2.0","method":"nothing","id":1},
+            {"jsonrpc":"2.0","method":"nothing"},
+            1,
+            {"jsonrpc":"2.0","method":"nope","id":2}
+        ]"#;
+        let response = handle(body, dispatch).unwrap();
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(responses[2]["error"]["code"], METHOD_NOT_FOUND);
+        assert!(handle(r#"[{"jsonrpc":"2.0","method":"nothing"}]"#, dispatch).is_none());
+    }
+}
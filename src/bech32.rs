@@ -0,0 +1,171 @@
+// Bech32m encoding (BIP-0350) of byte strings: a human-readable prefix, the separator `1`, the
+// data in base 32 and a six-character checksum that detects any error of up to four characters.
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const SEPARATOR: char = '1';
+const CHECKSUM_LENGTH: usize = 6;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+/// Maximum length of an encoded string
+const MAX_LENGTH: usize = 90;
+
+/// Reasons a string is not valid Bech32m.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The string has no `1` separating the prefix from the data
+    MissingSeparator,
+    /// The prefix is empty or the string is too long
+    InvalidLength,
+    /// The string mixes upper and lower case characters
+    MixedCase,
+    /// A character is not in the Bech32 alphabet, with its position
+    InvalidCharacter(char, usize),
+    /// The checksum does not match, so the string was mistyped
+    InvalidChecksum,
+    /// The data does not decode to whole bytes
+    InvalidPadding,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::MissingSeparator => write!(f, "missing separator '1'"),
+            Error::InvalidLength => write!(f, "invalid length"),
+            Error::MixedCase => write!(f, "mixed upper and lower case"),
+            Error::InvalidCharacter(c, position) => write!(f, "invalid character {:?} at position {}", c, position),
+            Error::InvalidChecksum => write!(f, "invalid checksum, check for typos"),
+            Error::InvalidPadding => write!(f, "invalid padding"),
+        }
+    }
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x01ff_ffff) << 5 ^ u32::from(value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// The prefix expanded for checksumming: high bits of each character, 0, then low bits.
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let bytes = prefix.as_bytes();
+    let mut expanded: Vec<u8> = bytes.iter().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(bytes.iter().map(|b| b & 31));
+    expanded
+}
+
+/// Regroup bits from `from`-bit to `to`-bit values. When encoding, the last group is padded with
+/// zeros; when decoding, the padding must be shorter than `from` bits and zero.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Error> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut out = vec![];
+    let max = (1 << to) - 1;
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err(Error::InvalidPadding);
+    }
+    Ok(out)
+}
+
+/// Encode `data` with the lower-case `prefix`.
+pub fn encode(prefix: &str, data: &[u8]) -> String {
+    let values = convert_bits(data, 8, 5, true).unwrap();
+    let mut checked = expand_prefix(prefix);
+    checked.extend(&values);
+    checked.extend(&[0; CHECKSUM_LENGTH]);
+    let checksum = polymod(checked.into_iter()) ^ BECH32M_CONST;
+    let mut encoded = String::with_capacity(prefix.len() + 1 + values.len() + CHECKSUM_LENGTH);
+    encoded.push_str(prefix);
+    encoded.push(SEPARATOR);
+    for value in values {
+        encoded.push(CHARSET[value as usize] as char);
+    }
+    for i in 0..CHECKSUM_LENGTH {
+        encoded.push(CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode a string into its lower-case prefix and its data.
+pub fn decode(s: &str) -> Result<(String, Vec<u8>), Error> {
+    if s.len() > MAX_LENGTH {
+        return Err(Error::InvalidLength);
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(Error::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let separator = s.rfind(SEPARATOR).ok_or(Error::MissingSeparator)?;
+    let (prefix, data) = (&s[..separator], &s[separator + 1..]);
+    if prefix.is_empty() || data.len() < CHECKSUM_LENGTH {
+        return Err(Error::InvalidLength);
+    }
+    if let Some((position, c)) = prefix.char_indices().find(|(_, c)| !('!'..='~').contains(c)) {
+        return Err(Error::InvalidCharacter(c, position));
+    }
+    let mut values = Vec::with_capacity(data.len());
+    for (i, c) in data.char_indices() {
+        match CHARSET.iter().position(|x| *x as char == c) {
+            Some(value) => values.push(value as u8),
+            None => return Err(Error::InvalidCharacter(c, separator + 1 + i)),
+        }
+    }
+    let mut checked = expand_prefix(prefix);
+    checked.extend(&values);
+    if polymod(checked.into_iter()) != BECH32M_CONST {
+        return Err(Error::InvalidChecksum);
+    }
+    values.truncate(values.len() - CHECKSUM_LENGTH);
+    Ok((prefix.to_string(), convert_bits(&values, 5, 8, false)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip350_vectors() {
+        for valid in &[
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "?1v759aa",
+        ] {
+            assert!(decode(valid).is_ok(), "{}", valid);
+        }
+        assert_eq!(decode("a1lqfn3a").unwrap(), ("a".to_string(), vec![]));
+        assert_eq!(decode("A1lqfn3a"), Err(Error::MixedCase));
+        assert_eq!(decode("a1lqfn3q"), Err(Error::InvalidChecksum));
+        assert_eq!(decode("alqfn3a"), Err(Error::MissingSeparator));
+        assert_eq!(decode("a1lqfnba"), Err(Error::InvalidCharacter('b', 6)));
+        assert_eq!(decode("1lqfn3a"), Err(Error::InvalidLength));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..20).collect();
+        let encoded = encode("bit", &data);
+        assert_eq!(decode(&encoded).unwrap(), ("bit".to_string(), data.clone()));
+        assert_eq!(decode(&encoded.to_uppercase()).unwrap(), ("bit".to_string(), data));
+    }
+}
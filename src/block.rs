@@ -1,6 +1,10 @@
 use serde::{Serialize, Deserialize};
+use siphasher::sip::SipHasher24;
+use std::convert::TryInto;
+use std::hash::Hasher;
 use crate::crypto::hash::{H256, Hashable};
-use crate::transaction::RawTransaction;
+use crate::crypto::merkle::MerkleTree;
+use crate::encoding::{Decode, DecodeError, Encode, Reader};
 use crate::transaction::SignedTransaction as Transaction;
 
 /// The block header
@@ -26,8 +30,53 @@ pub struct Block {
     pub content: Content,
 }
 
+/// A block as relayed between peers that likely already hold its transactions: the header plus
+/// the short IDs of the transactions, in block order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompactBlock {
+    pub header: Header,
+    pub short_ids: Vec<u64>,
+}
+
+/// The short ID of a transaction in the block `block_hash`: the SipHash-2-4 of the transaction
+/// hash, keyed by the first 16 bytes of the block hash as in BIP-152. Salting per block keeps
+/// attackers from precomputing transactions whose short IDs collide in every block.
+pub fn short_id(block_hash: &H256, tx_hash: &H256) -> u64 {
+    let key = block_hash.as_ref();
+    let k0 = u64::from_le_bytes(key[..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(key[8..16].try_into().unwrap());
+    let mut hasher = SipHasher24::new_with_keys(k0, k1);
+    hasher.write(tx_hash.as_ref());
+    hasher.finish()
+}
+
+impl CompactBlock {
+    /// Rebuild the block using `lookup` to resolve short IDs. Returns the positions of the
+    /// transactions that could not be resolved if the block is incomplete.
+    pub fn reconstruct<F>(&self, lookup: F) -> Result<Block, Vec<u32>>
+    where
+        F: Fn(u64) -> Option<Transaction>,
+    {
+        let mut transactions = Vec::with_capacity(self.short_ids.len());
+        let mut missing = Vec::new();
+        for (index, id) in self.short_ids.iter().enumerate() {
+            match lookup(*id) {
+                Some(tx) => transactions.push(tx),
+                None => missing.push(index as u32),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        Ok(Block {
+            header: self.header.clone(),
+            content: Content { transactions },
+        })
+    }
+}
+
 /// Returns the default difficulty, which is a big-endian 32-byte integer.
-/// - Note: a valid block must satisfy that `block.hash() <= difficulty`.
+/// - Note: a valid block must satisfy that `block.header.hash() <= difficulty`.
 ///   In other words, the _smaller_ the `difficulty`, the harder it actually is to mine a block!
 fn default_difficulty() -> [u8; 32] {
     // TODO: it's up to you to determine an appropriate difficulty.
@@ -38,8 +87,8 @@ fn default_difficulty() -> [u8; 32] {
     // difficulty
     let mut difficulty = [0xFF; 32];
     let leading_zeros = 1;
-    for i in 0..leading_zeros {
-        difficulty[i] = 0;
+    for byte in difficulty.iter_mut().take(leading_zeros) {
+        *byte = 0;
     }
     // hash < 0000010000000000000000000000000000000000000000000000000000000000
     //  e.g., 000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffffff
@@ -48,15 +97,82 @@ fn default_difficulty() -> [u8; 32] {
     difficulty
 }
 
+impl Hashable for Header {
+    /// Hash the canonical encoding of the header using SHA256.
+    fn hash(&self) -> H256 {
+        ring::digest::digest(&ring::digest::SHA256, &self.encode()).into()
+    }
+}
+
 impl Hashable for Block {
-    /// Hash the block header using SHA256.
+    /// The hash of the header, which identifies the block and is the proof of work. It commits to
+    /// the transactions only through the Merkle root, see `Block::has_valid_merkle_root`.
     fn hash(&self) -> H256 {
-        let bytes = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+        self.header.hash()
+    }
+}
+
+impl Content {
+    /// The Merkle root of the transactions, which is zero if there are none.
+    pub fn merkle_root(&self) -> H256 {
+        MerkleTree::new(&self.transactions).root()
+    }
+}
+
+impl Encode for Header {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.parent.encode_to(out);
+        self.nonce.encode_to(out);
+        self.difficulty.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.merkle_root.encode_to(out);
+    }
+}
+
+impl Decode for Header {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Header {
+            parent: Decode::decode_from(reader)?,
+            nonce: Decode::decode_from(reader)?,
+            difficulty: Decode::decode_from(reader)?,
+            timestamp: Decode::decode_from(reader)?,
+            merkle_root: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.content.transactions.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Decode::decode_from(reader)?,
+            content: Content { transactions: Decode::decode_from(reader)? },
+        })
     }
 }
 
 impl Block {
+    /// Whether the Merkle root in the header matches the transactions. The block hash does not
+    /// cover the transactions, so this must be checked before a received block is accepted.
+    pub fn has_valid_merkle_root(&self) -> bool {
+        self.header.merkle_root == self.content.merkle_root()
+    }
+
+    /// Build the compact form of this block
+    pub fn compact(&self) -> CompactBlock {
+        let hash = self.hash();
+        CompactBlock {
+            header: self.header.clone(),
+            short_ids: self.content.transactions.iter().map(|tx| short_id(&hash, &tx.hash())).collect(),
+        }
+    }
+
     /// Construct the (totally deterministic) genesis block
     pub fn genesis() -> Block {
         let transactions: Vec<Transaction> = vec![];
@@ -72,14 +188,17 @@ impl Block {
     }
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub mod test {
     use super::*;
     use crate::crypto::hash::H256;
-    use crate::crypto::merkle::MerkleTree;
 
     pub fn generate_random_block(parent: &H256) -> Block {
-        let transactions: Vec<Transaction> = vec![Default::default()];
+        generate_block(parent, vec![Default::default()])
+    }
+
+    /// A block with the given transactions and a random nonce and timestamp
+    pub fn generate_block(parent: &H256, transactions: Vec<Transaction>) -> Block {
         let root = MerkleTree::new(&transactions).root();
         let header = Header {
             parent: *parent,
@@ -91,4 +210,54 @@ pub mod test {
         let content = Content { transactions };
         Block { header, content }
     }
+
+    #[test]
+    fn compact_roundtrip() {
+        let block = generate_random_block(&Default::default());
+        let compact = block.compact();
+        assert_eq!(compact.short_ids.len(), block.content.transactions.len());
+        let pool = block.content.transactions.clone();
+        let rebuilt = compact
+            .reconstruct(|id| pool.iter().find(|tx| short_id(&block.hash(), &tx.hash()) == id).cloned())
+            .unwrap();
+        assert_eq!(rebuilt.hash(), block.hash());
+        assert_eq!(compact.reconstruct(|_| None).unwrap_err(), vec![0]);
+
+        // the same transaction has another short ID in another block
+        let other = generate_block(&Default::default(), pool.clone());
+        assert_ne!(other.compact().short_ids, compact.short_ids);
+    }
+
+    #[test]
+    fn hash_commits_to_header_only() {
+        let block = generate_random_block(&Default::default());
+        assert_eq!(block.hash(), block.header.hash());
+        assert!(block.has_valid_merkle_root());
+        assert!(Block::genesis().has_valid_merkle_root());
+
+        let mut tampered = block.clone();
+        tampered.content.transactions.push(Default::default());
+        assert_eq!(tampered.hash(), block.hash());
+        assert!(!tampered.has_valid_merkle_root());
+        tampered.header.merkle_root = tampered.content.merkle_root();
+        assert_ne!(tampered.hash(), block.hash());
+        assert!(tampered.has_valid_merkle_root());
+    }
+
+    #[test]
+    fn duplicated_transactions_change_the_merkle_root() {
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|value| {
+                let mut tx = Transaction::default();
+                tx.raw.value = value;
+                tx
+            })
+            .collect();
+        let block = generate_block(&Default::default(), transactions);
+        // a peer relaying the block with its last transaction repeated cannot keep its hash valid
+        let mut mutated = block.clone();
+        mutated.content.transactions.push(block.content.transactions[2].clone());
+        assert_eq!(mutated.hash(), block.hash());
+        assert!(!mutated.has_valid_merkle_root());
+    }
 }
@@ -1,43 +1,120 @@
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 
-use crate::address::{get_deterministic_keypair, H160};
+use crate::address::{default_genesis_seed, ico_keypair, H160, ICO_ACCOUNTS};
 use crate::block::Block;
+use crate::transaction::{verify_batch, SignedTransaction as Transaction};
 use crate::crypto::hash::{Hashable, H256};
-use log::{debug, warn};
+use crate::encoding::Encode;
+use crate::mempool::serialize_display;
+use crate::metrics::metrics;
+use crate::spv::InclusionProof;
+use crossbeam::channel::{self, Receiver, Sender};
+use log::warn;
 use ring::signature::KeyPair;
 use serde::Serialize;
 
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Clone)]
 
 pub enum BlockOrigin {
     Mined,
     Received{delay_ms: u128},
 }
 
+/// Changes to the blockchain, delivered to subscribers.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    /// A block was inserted into the blockchain (orphans are only reported once inserted)
+    BlockAccepted {
+        #[serde(serialize_with = "serialize_display")]
+        hash: H256,
+        #[serde(serialize_with = "serialize_display")]
+        parent: H256,
+        height: u64,
+        origin: BlockOrigin,
+        transactions: usize,
+    },
+    /// The tip of the longest chain moved. `reorg_depth` is the number of blocks that left the
+    /// longest chain, i.e. 0 if the new tip extends the old one.
+    TipChanged {
+        #[serde(serialize_with = "serialize_display")]
+        old_tip: H256,
+        #[serde(serialize_with = "serialize_display")]
+        new_tip: H256,
+        height: u64,
+        reorg_depth: u64,
+    },
+}
+
+impl ChainEvent {
+    /// The event type, as used in the serialized `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChainEvent::BlockAccepted { .. } => "block_accepted",
+            ChainEvent::TipChanged { .. } => "tip_changed",
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Debug)]
 pub struct State {
-    map: HashMap<H160, (u32, u64)>
+    map: HashMap<H160, (u32, u64)>,
+    // the chain and the height of the block this is the state after
+    chain_id: H256,
+    height: u64,
 }
 
 impl State {
     /// Initial coin offering; generate an initial state.
-    fn ico() -> Self {
+    fn ico(genesis_seed: &[u8]) -> Self {
         let mut state = HashMap::new();
         // give the i-th account 1000 * (10 - i) coins, i = 0, 1, 2, ..., 9
-        for i in 0..10 {
-            let pair = get_deterministic_keypair(i);
+        for i in 0..ICO_ACCOUNTS {
+            let pair = ico_keypair(genesis_seed, i);
             let address = H160::from_pubkey(pair.public_key().as_ref());
-            let balance: u64 = 1000 * ((10 - i) as u64);
+            let balance: u64 = 1000 * ((ICO_ACCOUNTS - i) as u64);
             let nonce: u32 = 0;
             state.insert(address, (nonce, balance));
         }
-        State { map: state }
+        State { map: state, chain_id: H256::default(), height: 0 }
+    }
+
+    /// The chain this is a state of, see `Blockchain::chain_id`
+    pub fn chain_id(&self) -> H256 {
+        self.chain_id
+    }
+
+    /// The height of the block this is the state after
+    pub fn height(&self) -> u64 {
+        self.height
     }
 
     // Get the state of an account
     pub fn get(&self, address: &H160) -> Option<&(u32, u64)> {
         self.map.get(address)
     }
+
+    /// Get all accounts as `(address, (nonce, balance))`, sorted by address
+    pub fn accounts(&self) -> Vec<(H160, (u32, u64))> {
+        let mut accounts: Vec<(H160, (u32, u64))> =
+            self.map.iter().map(|(address, account)| (*address, *account)).collect();
+        accounts.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        accounts
+    }
+}
+
+impl Hashable for State {
+    /// The state root: SHA256 over every account's address, nonce and balance, in address order.
+    fn hash(&self) -> H256 {
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        for (address, (nonce, balance)) in self.accounts() {
+            ctx.update(address.as_ref());
+            ctx.update(&nonce.to_be_bytes());
+            ctx.update(&balance.to_be_bytes());
+        }
+        ctx.finish().into()
+    }
 }
 
 #[derive(Serialize)]
@@ -50,11 +127,43 @@ pub struct Blockchain {
     orphan_buffer: HashMap<H256, Vec<Block>>,
     pub hash_to_origin: HashMap<H256, BlockOrigin>,
     pub hash_to_state: HashMap<H256, State>,
+    // index from a transaction hash to the blocks applying it (on any fork)
+    #[serde(skip)]
+    tx_to_blocks: HashMap<H256, Vec<H256>>,
+    #[serde(skip)]
+    subscribers: Vec<Sender<ChainEvent>>,
+    // serialized size of all blocks
+    #[serde(skip)]
+    block_bytes: u64,
+    chain_id: H256,
+}
+
+/// The chain ID: a hash of the genesis block and the ICO state root. Networks with different
+/// genesis seeds have different ICO accounts and thus different chain IDs.
+fn chain_id(genesis_hash: &H256, ico: &State) -> H256 {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(b"bitcoin/chain-id/v1");
+    ctx.update(genesis_hash.as_ref());
+    ctx.update(ico.hash().as_ref());
+    ctx.finish().into()
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Blockchain {
-    /// Create a new blockchain, only containing the genesis block
+    /// Create a new blockchain, only containing the genesis block, with the ICO accounts of the
+    /// default genesis seed
     pub fn new() -> Self {
+        Self::with_genesis_seed(&default_genesis_seed())
+    }
+
+    /// Create a new blockchain, only containing the genesis block, with the ICO accounts derived
+    /// from `genesis_seed`
+    pub fn with_genesis_seed(genesis_seed: &[u8]) -> Self {
         let genesis = Block::genesis();
         let genesis_hash = genesis.hash();
         let mut hash_to_block = HashMap::new();
@@ -63,27 +172,57 @@ impl Blockchain {
         let mut hash_to_state = HashMap::new();
         hash_to_length.insert(genesis_hash, 0);
         hash_to_block.insert(genesis_hash, genesis);
-        hash_to_state.insert(genesis_hash, State::ico());
+        let mut ico = State::ico(genesis_seed);
+        let chain_id = chain_id(&genesis_hash, &ico);
+        ico.chain_id = chain_id;
+        hash_to_state.insert(genesis_hash, ico);
+        let block_bytes = hash_to_block[&genesis_hash].encode().len() as u64;
 
         Blockchain {
             tip: genesis_hash,
-            hash_to_length: hash_to_length,
-            hash_to_block: hash_to_block,
+            hash_to_length,
+            hash_to_block,
             orphan_buffer: HashMap::new(),
             hash_to_origin: HashMap::new(),
-            hash_to_state: hash_to_state,
+            hash_to_state,
+            tx_to_blocks: HashMap::new(),
+            subscribers: vec![],
+            block_bytes,
+            chain_id,
         }
     }
 
-    /// Insert a block into blockchain
+    /// The ID of this chain, which transactions are signed for
+    pub fn chain_id(&self) -> H256 {
+        self.chain_id
+    }
+
+    /// Subscribe to blockchain events
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn publish(&mut self, event: ChainEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Insert a mined block into blockchain
     pub fn insert(&mut self, block: &Block) {
+        self.insert_with_origin(block, BlockOrigin::Mined);
+    }
+
+    /// Insert a block into blockchain, recording where it came from
+    pub fn insert_with_origin(&mut self, block: &Block, origin: BlockOrigin) {
         let block_hash = block.hash();
+        let old_tip = self.tip;
         self.hash_to_block.insert(block_hash, block.clone());
         let parent_hash = block.header.parent;
         let length: u64 = *self.hash_to_length.get(&parent_hash).unwrap() + 1;
         self.hash_to_length.insert(block_hash, length);
-        let mined = BlockOrigin::Mined;
-        self.hash_to_origin.insert(block.hash(), mined);
+        self.hash_to_origin.insert(block_hash, origin.clone());
         if length > *self.hash_to_length.get(&self.tip).unwrap() {
             self.tip = block_hash;
         }
@@ -92,13 +231,31 @@ impl Blockchain {
         // update the account nonce and balance
         // TODO: do double spending check here?
         let new_state = State {
+            chain_id: self.chain_id,
+            height: length,
             map: {
                 let mut state = self.hash_to_state.get(&parent_hash).unwrap().map.clone();
-                for tx in &block.content.transactions {
-                    let sender = H160::from_pubkey(&tx.pub_key);
+                let signatures_valid = verify_batch(&block.content.transactions);
+                for (tx, signature_valid) in block.content.transactions.iter().zip(signatures_valid) {
+                    let sender = tx.signer();
                     // println!("Processing transaction from: {:?}", sender);
                     // println!("Transaction: {:?}", tx);
-                    assert!(sender == tx.raw.from_addr);
+                    if sender != tx.raw.from_addr {
+                        warn!("Transaction from_addr {:?} does not match the address of its signer {:?}", tx.raw.from_addr, sender);
+                        continue
+                    }
+                    if tx.raw.chain_id != self.chain_id {
+                        warn!("Transaction from {:?} is signed for another chain {:?}", sender, tx.raw.chain_id);
+                        continue
+                    }
+                    if tx.raw.expiry_height.is_some_and(|expiry| expiry < length) {
+                        warn!("Transaction from {:?} expired at height {:?}", sender, tx.raw.expiry_height);
+                        continue
+                    }
+                    if !signature_valid {
+                        warn!("Transaction from {:?} does not carry valid signatures", sender);
+                        continue
+                    }
                     let (sender_nonce, sender_balance) = state.get(&sender).unwrap_or(&(0, 0));  // get the sender's nonce and balance, if not found, initialize with 0
                     if sender_nonce + 1 != tx.raw.nonce {
                         // check the nonce
@@ -106,18 +263,66 @@ impl Blockchain {
                         // assert!(sender_nonce + 1 == tx.raw.nonce);  // check the nonce
                         continue
                     }
-                    assert!(sender_balance >= &tx.raw.value);  // check the balance
+                    if sender_balance < &tx.raw.value {
+                        // check the balance
+                        warn!("Transaction value exceeds the sender's balance: {:?} vs {:?}", tx.raw.value, sender_balance);
+                        continue
+                    }
                     // debug!("wired amount: {:?}", tx.raw.value);
                     state.insert(sender, (sender_nonce + 1, sender_balance - tx.raw.value));
                     let receiver = tx.raw.to_addr;
                     let (receiver_nonce, receiver_balance) = state.get(&receiver).unwrap_or(&(0, 0));
                     state.insert(receiver, (*receiver_nonce, receiver_balance + tx.raw.value));
+                    // only index the transactions the block applies
+                    self.tx_to_blocks.entry(tx.hash()).or_default().push(block_hash);
                 }
                 state
             }
         };
 
         self.hash_to_state.insert(block_hash, new_state);
+
+        self.block_bytes += block.encode().len() as u64;
+        let m = metrics();
+        m.chain_blocks.store(self.hash_to_block.len() as u64, Ordering::Relaxed);
+        m.chain_height.store(self.length_of_longest_chain(), Ordering::Relaxed);
+        m.chain_bytes.store(self.block_bytes, Ordering::Relaxed);
+        if let BlockOrigin::Received { delay_ms } = origin {
+            m.blocks_received.fetch_add(1, Ordering::Relaxed);
+            m.propagation_delay_ms.fetch_add(delay_ms as u64, Ordering::Relaxed);
+        }
+
+        self.publish(ChainEvent::BlockAccepted {
+            hash: block_hash,
+            parent: parent_hash,
+            height: length,
+            origin,
+            transactions: block.content.transactions.len(),
+        });
+        if self.tip != old_tip {
+            let fork = self.common_ancestor(old_tip, block_hash);
+            self.publish(ChainEvent::TipChanged {
+                old_tip,
+                new_tip: block_hash,
+                height: length,
+                reorg_depth: self.get_length(&old_tip) - self.get_length(&fork),
+            });
+        }
+    }
+
+    /// The most recent block that both `a` and `b` descend from
+    fn common_ancestor(&self, mut a: H256, mut b: H256) -> H256 {
+        while self.get_length(&a) > self.get_length(&b) {
+            a = self.hash_to_block[&a].header.parent;
+        }
+        while self.get_length(&b) > self.get_length(&a) {
+            b = self.hash_to_block[&b].header.parent;
+        }
+        while a != b {
+            a = self.hash_to_block[&a].header.parent;
+            b = self.hash_to_block[&b].header.parent;
+        }
+        a
     }
 
     /// Get the last block's hash of the longest chain
@@ -144,11 +349,29 @@ impl Blockchain {
         blocks.iter().map(|block| block.hash()).collect()
     }
 
+    /// Get the hash of the block at `height` on the longest chain, if the chain is that long
+    pub fn hash_at_height(&self, height: u64) -> Option<H256> {
+        let tip_height = self.length_of_longest_chain();
+        if height > tip_height {
+            return None;
+        }
+        let mut current_hash = self.tip;
+        for _ in height..tip_height {
+            current_hash = self.hash_to_block.get(&current_hash).unwrap().header.parent;
+        }
+        Some(current_hash)
+    }
+
     /// Get the number of blocks in the whole blockchain
     pub fn len(&self) -> usize {
         self.hash_to_block.len()
     }
 
+    /// Check if the blockchain has no blocks (never true, since genesis is always present)
+    pub fn is_empty(&self) -> bool {
+        self.hash_to_block.is_empty()
+    }
+
     /// Get the length of the longest chain
     pub fn length_of_longest_chain(&self) -> u64 {
         *self.hash_to_length.get(&self.tip).unwrap()
@@ -182,6 +405,35 @@ impl Blockchain {
         hashes.iter().filter_map(|hash| self.get_block(hash)).collect()
     }
 
+    /// Get the hashes of the blocks applying a transaction; blocks that skip it are left out
+    pub fn transaction_blocks(&self, tx_hash: &H256) -> &[H256] {
+        self.tx_to_blocks.get(tx_hash).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Get the hash of the block on the longest chain containing a transaction, if any
+    pub fn confirming_block(&self, tx_hash: &H256) -> Option<H256> {
+        self.transaction_blocks(tx_hash)
+            .iter()
+            .find(|block| self.hash_at_height(self.get_length(block)) == Some(**block))
+            .copied()
+    }
+
+    /// Get the inclusion proof of a transaction in its block on the longest chain, if any
+    pub fn inclusion_proof(&self, tx_hash: &H256) -> Option<InclusionProof> {
+        let block = self.confirming_block(tx_hash)?;
+        InclusionProof::new(&self.hash_to_block[&block], tx_hash)
+    }
+
+    /// Get a transaction from any block containing it
+    pub fn get_transaction(&self, tx_hash: &H256) -> Option<&Transaction> {
+        let block_hash = self.transaction_blocks(tx_hash).first()?;
+        self.hash_to_block[block_hash]
+            .content
+            .transactions
+            .iter()
+            .find(|tx| tx.hash() == *tx_hash)
+    }
+
     /// Check if the blockchain contains a block
     pub fn contains_block(&self, hash: &H256) -> bool {
         self.hash_to_block.contains_key(hash)
@@ -199,6 +451,7 @@ impl Blockchain {
     /// Insert to orphan buffer
     pub fn insert_orphan(&mut self, hash: H256, block: Block) {
         self.orphan_buffer.entry(hash).or_insert(vec![]).push(block.clone());
+        self.update_orphan_metric();
         // println!("Inserting orphan block: {:?} with hash: {:?}", block, hash);
         // println!("Orphan buffer: {:?}", self.orphan_buffer);
     }
@@ -206,6 +459,12 @@ impl Blockchain {
     /// Remove from orphan buffer
     pub fn remove_orphans(&mut self, hash: &H256) {
         self.orphan_buffer.remove(hash);
+        self.update_orphan_metric();
+    }
+
+    fn update_orphan_metric(&self) {
+        let orphans: usize = self.orphan_buffer.values().map(Vec::len).sum();
+        metrics().orphan_blocks.store(orphans as u64, Ordering::Relaxed);
     }
 
     // pub fn contains_transaction(&self, hash: &H256) -> bool {
@@ -218,10 +477,11 @@ impl Blockchain {
 
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 mod tests {
     use super::*;
     use crate::block::test::generate_random_block;
+    use crate::mempool;
     use crate::crypto::hash::Hashable;
 
     #[test]
@@ -276,4 +536,216 @@ mod tests {
         assert_eq!(blockchain.tip(), block_5.hash());
     }
 
+    #[test]
+    fn transaction_index() {
+        use crate::address::{default_genesis_seed, ico_keypair};
+        use crate::block::test::generate_block;
+        use crate::transaction::{RawTransaction, SignedTransaction};
+        use ring::signature::KeyPair;
+
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let ico = ico_keypair(&default_genesis_seed(), 0);
+        let raw = RawTransaction {
+            from_addr: H160::from_pubkey(ico.public_key().as_ref()),
+            to_addr: H160::default(),
+            value: 10,
+            nonce: 1,
+            chain_id: blockchain.chain_id(),
+            expiry_height: None,
+        };
+        let tx = SignedTransaction::from_raw(raw, &ico);
+        let block_1 = generate_block(&genesis_hash, vec![tx.clone()]);
+        blockchain.insert(&block_1);
+        let fork_block_1 = generate_block(&genesis_hash, vec![tx.clone()]);
+        blockchain.insert(&fork_block_1);
+        assert_eq!(blockchain.transaction_blocks(&tx.hash()), &[block_1.hash(), fork_block_1.hash()]);
+        assert_eq!(blockchain.get_transaction(&tx.hash()).unwrap().hash(), tx.hash());
+        assert!(blockchain.transaction_blocks(&genesis_hash).is_empty());
+
+        // the nonce is used up, so the child block skips the transaction
+        let block_2 = generate_block(&block_1.hash(), vec![tx.clone()]);
+        blockchain.insert(&block_2);
+        assert_eq!(blockchain.transaction_blocks(&tx.hash()), &[block_1.hash(), fork_block_1.hash()]);
+        // random blocks all carry the default transaction, which is never applied
+        let random = generate_random_block(&block_2.hash());
+        blockchain.insert(&random);
+        assert!(blockchain.transaction_blocks(&random.content.transactions[0].hash()).is_empty());
+    }
+
+    #[test]
+    fn state_root() {
+        let blockchain = Blockchain::new();
+        let ico = blockchain.state();
+        assert_eq!(ico.hash(), Blockchain::new().state().hash());
+        assert_eq!(ico.accounts().len(), 10);
+        let mut state = ico.clone();
+        let (address, (nonce, balance)) = state.accounts()[0];
+        state.map.insert(address, (nonce, balance - 1));
+        assert_ne!(state.hash(), ico.hash());
+        state.map.insert(address, (nonce, balance));
+        assert_eq!(state.hash(), ico.hash());
+    }
+
+    #[test]
+    fn ico_from_genesis_seed() {
+        use crate::address::{default_genesis_seed, ico_keypair};
+        use crate::crypto::hd::seed_from_phrase;
+        use ring::signature::KeyPair;
+
+        let seed = seed_from_phrase("another network", "");
+        let state = Blockchain::with_genesis_seed(&seed).state();
+        assert_ne!(state.hash(), Blockchain::new().state().hash());
+        for i in 0..ICO_ACCOUNTS {
+            let address = H160::from_pubkey(ico_keypair(&seed, i).public_key().as_ref());
+            assert_eq!(state.get(&address), Some(&(0, 1000 * (ICO_ACCOUNTS - i) as u64)));
+        }
+        let default_first = H160::from_pubkey(ico_keypair(&default_genesis_seed(), 0).public_key().as_ref());
+        assert_eq!(state.get(&default_first), None);
+    }
+
+    #[test]
+    fn multisig_account() {
+        use crate::address::{default_genesis_seed, ico_keypair};
+        use crate::block::test::generate_block;
+        use crate::crypto::key_pair;
+        use crate::transaction::{MultisigPolicy, RawTransaction, SignedTransaction};
+        use ring::signature::KeyPair;
+
+        let keys: Vec<_> = (0..3).map(|_| key_pair::random()).collect();
+        let policy = MultisigPolicy::new(2, keys.iter().map(|key| key.public_key().as_ref().to_vec()).collect()).unwrap();
+        let account = policy.address();
+        let ico = ico_keypair(&default_genesis_seed(), 0);
+        let ico_address = H160::from_pubkey(ico.public_key().as_ref());
+        let mut blockchain = Blockchain::new();
+        let chain_id = blockchain.chain_id();
+        let fund = RawTransaction {
+            from_addr: ico_address,
+            to_addr: account,
+            value: 100,
+            nonce: 1,
+            chain_id,
+            expiry_height: None,
+        };
+
+        let block_1 = generate_block(&blockchain.tip(), vec![SignedTransaction::from_raw(fund, &ico)]);
+        blockchain.insert(&block_1);
+        assert_eq!(blockchain.state().get(&account), Some(&(0, 100)));
+
+        let spend = RawTransaction {
+            from_addr: account,
+            to_addr: ico_address,
+            value: 40,
+            nonce: 1,
+            chain_id,
+            expiry_height: None,
+        };
+        let one_signature = SignedTransaction::from_multisig(spend.clone(), policy.clone(), &[&keys[0]]);
+        assert_eq!(
+            mempool::validate(&blockchain.state(), &one_signature),
+            Err(mempool::TransactionError::InvalidSignature)
+        );
+        let block_2 = generate_block(&block_1.hash(), vec![one_signature]);
+        blockchain.insert(&block_2);
+        assert_eq!(blockchain.state().get(&account), Some(&(0, 100)));
+
+        let two_signatures = SignedTransaction::from_multisig(spend, policy, &[&keys[0], &keys[2]]);
+        assert_eq!(mempool::validate(&blockchain.state(), &two_signatures), Ok(()));
+        blockchain.insert(&generate_block(&block_2.hash(), vec![two_signatures]));
+        assert_eq!(blockchain.state().get(&account), Some(&(1, 60)));
+    }
+
+    #[test]
+    fn hash_at_height_follows_longest_chain() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&block_1);
+        let fork_block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&fork_block_1);
+        let fork_block_2 = generate_random_block(&fork_block_1.hash());
+        blockchain.insert(&fork_block_2);
+        assert_eq!(blockchain.hash_at_height(0), Some(genesis_hash));
+        assert_eq!(blockchain.hash_at_height(1), Some(fork_block_1.hash()));
+        assert_eq!(blockchain.hash_at_height(2), Some(fork_block_2.hash()));
+        assert_eq!(blockchain.hash_at_height(3), None);
+    }
+
+    #[test]
+    fn events_report_blocks_and_reorgs() {
+        let mut blockchain = Blockchain::new();
+        let events = blockchain.subscribe();
+        let genesis_hash = blockchain.tip();
+        let block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&block_1);
+        let fork_block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert_with_origin(&fork_block_1, BlockOrigin::Received { delay_ms: 5 });
+        let fork_block_2 = generate_random_block(&fork_block_1.hash());
+        blockchain.insert(&fork_block_2);
+        let events: Vec<ChainEvent> = events.try_iter().collect();
+        let names: Vec<&str> = events.iter().map(ChainEvent::name).collect();
+        assert_eq!(names, ["block_accepted", "tip_changed", "block_accepted", "block_accepted", "tip_changed"]);
+        match &events[2] {
+            ChainEvent::BlockAccepted { hash, height, origin: BlockOrigin::Received { delay_ms }, .. } => {
+                assert_eq!(*hash, fork_block_1.hash());
+                assert_eq!(*height, 1);
+                assert_eq!(*delay_ms, 5);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        match &events[4] {
+            ChainEvent::TipChanged { old_tip, new_tip, height, reorg_depth } => {
+                assert_eq!(*old_tip, block_1.hash());
+                assert_eq!(*new_tip, fork_block_2.hash());
+                assert_eq!(*height, 2);
+                assert_eq!(*reorg_depth, 1);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn skip_transaction_not_sent_by_signer() {
+        use crate::crypto::key_pair;
+        use crate::transaction::SignedTransaction;
+
+        let mut blockchain = Blockchain::new();
+        let mut unsigned = SignedTransaction::default();
+        unsigned.raw.value = 1;
+        unsigned.raw.nonce = 1;
+        // signed by a key whose address is not `from_addr`
+        let tx = SignedTransaction::from_raw(unsigned.raw, &key_pair::random());
+        let mut block = generate_random_block(&blockchain.tip());
+        block.content.transactions = vec![tx];
+        blockchain.insert(&block);
+        assert_eq!(blockchain.tip(), block.hash());
+        assert!(blockchain.state().get(&H160::default()).is_none());
+    }
+
+    #[test]
+    fn skip_overspending_transaction() {
+        use crate::address::{default_genesis_seed, ico_keypair};
+        use crate::block::test::generate_block;
+        use crate::transaction::{RawTransaction, SignedTransaction};
+        use ring::signature::KeyPair;
+
+        let ico = ico_keypair(&default_genesis_seed(), 9);
+        let ico_address = H160::from_pubkey(ico.public_key().as_ref());
+        let mut blockchain = Blockchain::new();
+        let (_, balance) = *blockchain.state().get(&ico_address).unwrap();
+        let overspend = RawTransaction {
+            from_addr: ico_address,
+            to_addr: H160::default(),
+            value: balance + 1,
+            nonce: 1,
+            chain_id: blockchain.chain_id(),
+            expiry_height: None,
+        };
+        let block = generate_block(&blockchain.tip(), vec![SignedTransaction::from_raw(overspend, &ico)]);
+        blockchain.insert(&block);
+        assert_eq!(blockchain.tip(), block.hash());
+        assert_eq!(blockchain.state().get(&ico_address), Some(&(0, balance)));
+        assert!(blockchain.state().get(&H160::default()).is_none());
+    }
+
 }
@@ -45,6 +45,17 @@ impl std::fmt::Debug for H256 {
     }
 }
 
+impl std::str::FromStr for H256 {
+    type Err = hex::FromHexError;
+
+    /// Parse a hash from 64 hex digits, as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 32] = [0; 32];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H256(buffer))
+    }
+}
+
 impl std::convert::AsRef<[u8]> for H256 {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -107,7 +118,7 @@ impl PartialOrd for H256 {
     }
 }
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 pub mod tests {
     use super::H256;
     use rand::Rng;
@@ -120,4 +131,12 @@ pub mod tests {
         (&raw_bytes).into()
     }
 
+    #[test]
+    fn hex_roundtrip() {
+        let hash = generate_random_hash();
+        assert_eq!(hash.to_string().parse::<H256>().unwrap(), hash);
+        assert!("00".parse::<H256>().is_err());
+        assert!("zz".repeat(32).parse::<H256>().is_err());
+    }
+
 }
@@ -0,0 +1,198 @@
+use ring::signature::Ed25519KeyPair;
+use ring::{hmac, pbkdf2};
+use std::num::NonZeroU32;
+use unicode_normalization::UnicodeNormalization;
+
+// Hierarchical deterministic derivation of Ed25519 keys as specified by SLIP-0010. Ed25519 only
+// supports hardened derivation, so every index of a derivation path is hardened. Master seeds are
+// usually derived from a seed phrase the way BIP-0039 does it.
+
+/// Indices at or above this are hardened.
+pub const HARDENED: u32 = 0x8000_0000;
+const MASTER_KEY: &[u8] = b"ed25519 seed";
+/// PBKDF2 iterations of BIP-0039 seed derivation
+const PHRASE_ITERATIONS: u32 = 2048;
+
+/// Turn a seed phrase and an optional passphrase into a 64-byte master seed, as BIP-0039 does.
+/// The phrase is not checked against a word list, so any phrase can be used.
+pub fn seed_from_phrase(phrase: &str, passphrase: &str) -> [u8; 64] {
+    // words are separated by single spaces, and both strings are NFKD-normalized
+    let phrase: String = phrase.split_whitespace().collect::<Vec<_>>().join(" ").nfkd().collect();
+    let salt: String = format!("mnemonic{}", passphrase).nfkd().collect();
+    let mut seed = [0u8; 64];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        NonZeroU32::new(PHRASE_ITERATIONS).unwrap(),
+        salt.as_bytes(),
+        phrase.as_bytes(),
+        &mut seed,
+    );
+    seed
+}
+
+/// A path of hardened child indices, written like `m/44'/0'/1'`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// The path of the given hardened indices, which must be below `HARDENED`.
+    pub fn new(indices: &[u32]) -> Self {
+        assert!(indices.iter().all(|index| *index < HARDENED));
+        DerivationPath(indices.iter().map(|index| index | HARDENED).collect())
+    }
+
+    /// The path one level deeper.
+    pub fn child(&self, index: u32) -> Self {
+        assert!(index < HARDENED);
+        let mut indices = self.0.clone();
+        indices.push(index | HARDENED);
+        DerivationPath(indices)
+    }
+
+    /// The child indices, including the hardened bit.
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            write!(f, "/{}'", index & !HARDENED)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for DerivationPath {
+    type Err = String;
+
+    /// Parse a path like `m/44'/0'/1'`. Hardened indices may be marked with `'` or `h`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('/');
+        if parts.next() != Some("m") {
+            return Err("derivation path must start with m".to_string());
+        }
+        let mut indices = vec![];
+        for part in parts {
+            let index = part
+                .strip_suffix('\'')
+                .or_else(|| part.strip_suffix('h'))
+                .ok_or_else(|| format!("index {} is not hardened, Ed25519 only supports hardened derivation", part))?;
+            let index: u32 = index.parse().map_err(|e| format!("invalid index {}: {}", part, e))?;
+            if index >= HARDENED {
+                return Err(format!("index {} is too large", part));
+            }
+            indices.push(index | HARDENED);
+        }
+        Ok(DerivationPath(indices))
+    }
+}
+
+/// A private key together with the chain code used to derive its children.
+#[derive(Clone)]
+pub struct ExtendedKey {
+    /// The Ed25519 seed of the key
+    pub key: [u8; 32],
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut ctx = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA512, key));
+        for part in data {
+            ctx.update(part);
+        }
+        let tag = ctx.sign();
+        let mut extended = ExtendedKey {
+            key: [0; 32],
+            chain_code: [0; 32],
+        };
+        extended.key.copy_from_slice(&tag.as_ref()[..32]);
+        extended.chain_code.copy_from_slice(&tag.as_ref()[32..]);
+        extended
+    }
+
+    /// The master key of a seed.
+    pub fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(MASTER_KEY, &[seed])
+    }
+
+    /// The hardened child with `index`, which must include the hardened bit.
+    pub fn child(&self, index: u32) -> Self {
+        assert!(index >= HARDENED, "Ed25519 only supports hardened derivation");
+        Self::from_hmac(&self.chain_code, &[&[0], &self.key, &index.to_be_bytes()])
+    }
+
+    /// The descendant at `path`.
+    pub fn derive(&self, path: &DerivationPath) -> Self {
+        path.indices().iter().fold(self.clone(), |key, index| key.child(*index))
+    }
+
+    pub fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.key).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair;
+
+    // SLIP-0010 test vector 1 for ed25519: (path, chain code, private key, public key)
+    const VECTOR_1: &[(&str, &str, &str, &str)] = &[
+        (
+            "m",
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+            "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+        ),
+        (
+            "m/0'",
+            "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+            "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+        ),
+        (
+            "m/0'/1'",
+            "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+            "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+            "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+        ),
+    ];
+
+    #[test]
+    fn slip10_vectors() {
+        let master = ExtendedKey::master(&hex!("000102030405060708090a0b0c0d0e0f"));
+        for (path, chain_code, key, public_key) in VECTOR_1 {
+            let derived = master.derive(&path.parse().unwrap());
+            assert_eq!(hex::encode(derived.chain_code), *chain_code, "{}", path);
+            assert_eq!(hex::encode(derived.key), *key, "{}", path);
+            assert_eq!(hex::encode(derived.key_pair().public_key()), *public_key, "{}", path);
+        }
+    }
+
+    #[test]
+    fn bip39_seed() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        assert_eq!(
+            hex::encode(&seed_from_phrase(phrase, "TREZOR")[..]),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        // extra whitespace does not change the seed
+        assert_eq!(seed_from_phrase(&format!(" {} ", phrase.replace(' ', "  ")), "TREZOR")[..], seed_from_phrase(phrase, "TREZOR")[..]);
+    }
+
+    #[test]
+    fn path_parsing() {
+        let path: DerivationPath = "m/44'/0h/7'".parse().unwrap();
+        assert_eq!(path, DerivationPath::new(&[44, 0, 7]));
+        assert_eq!(path.to_string(), "m/44'/0'/7'");
+        assert_eq!(DerivationPath::new(&[44]).child(0), DerivationPath::new(&[44, 0]));
+        assert_eq!("m".parse::<DerivationPath>().unwrap(), DerivationPath::default());
+        assert!("m/44".parse::<DerivationPath>().is_err());
+        assert!("44'".parse::<DerivationPath>().is_err());
+        assert!("m/2147483648'".parse::<DerivationPath>().is_err());
+    }
+}
@@ -2,16 +2,28 @@ use ring::digest;
 
 use super::hash::{Hashable, H256};
 
+// Leaves and internal nodes are hashed with different prefixes, so that an internal node can
+// never pass for a leaf or the other way round (a second preimage). The leaf level is padded to a
+// power of two with zero hashes rather than copies of the last leaf, so that [a, b, c] and
+// [a, b, c, c] have different roots (CVE-2012-2459). No leaf or node hashes to zero, and the root
+// of a tree without leaves is zero.
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
 /// helper functions
-fn hash_children(left: &H256, right: &H256) -> H256 {
-    // concatenate the left and right children, then hash the result
-    digest::digest(&digest::SHA256, &[left.as_ref(), right.as_ref()].concat()).into()
+fn hash_leaf(datum: &H256) -> H256 {
+    digest::digest(&digest::SHA256, &[&[LEAF_PREFIX], datum.as_ref()].concat()).into()
 }
 
+fn hash_children(left: &H256, right: &H256) -> H256 {
+    // concatenate the prefix and the left and right children, then hash the result
+    digest::digest(&digest::SHA256, &[&[NODE_PREFIX], left.as_ref(), right.as_ref()].concat()).into()
+}
 
-fn duplicate_last_node(curr_level: &mut Vec<H256>) {
-    let last_node = curr_level.last().unwrap().clone();
-    curr_level.push(last_node);
+/// The number of levels above the leaves of a tree with `leaf_count` leaves.
+fn depth(leaf_count: usize) -> usize {
+    leaf_count.next_power_of_two().trailing_zeros() as usize
 }
 
 
@@ -20,39 +32,37 @@ fn duplicate_last_node(curr_level: &mut Vec<H256>) {
 pub struct MerkleTree {
     pub array: Vec<H256>,
     level_count: usize, // how many levels the tree has
+    leaf_count: usize,
 }
 
 impl MerkleTree {
     pub fn new<T>(data: &[T]) -> Self where T: Hashable, {
-        assert!(!data.is_empty());
+        if data.is_empty() {
+            return MerkleTree {
+                array: vec![H256::default()],
+                level_count: 1,
+                leaf_count: 0,
+            };
+        }
 
         // create the leaf nodes:
-        let mut curr_level: Vec<H256> = Vec::new();
-        for item in data {
-            curr_level.push(item.hash());
-        }
+        let mut curr_level: Vec<H256> = data.iter().map(|item| hash_leaf(&item.hash())).collect();
         let mut level_count = 1;
 
-        // Make sure the number of leaves is a power of 2, if not, duplicate the last leaf until it is.
-        while curr_level.len() & (curr_level.len() - 1) != 0 {
-            duplicate_last_node(&mut curr_level);
-        }
-        assert!(curr_level.len().is_power_of_two());
+        // Make sure the number of leaves is a power of 2, if not, pad with zero hashes until it is.
+        curr_level.resize(data.len().next_power_of_two(), H256::default());
 
         let mut array: Vec<H256> = curr_level.clone();
         while curr_level.len() > 1 {
             // Bottom-up construction of the Merkle tree
-            let mut next_level: Vec<H256> = Vec::new();
-            for i in 0 .. curr_level.len() / 2 {
-                let left = curr_level[i * 2];
-                let right = curr_level[i * 2 + 1];
-                let hash = hash_children(&left, &right);
-                next_level.push(hash);
-            }
-            
+            let next_level: Vec<H256> = curr_level
+                .chunks(2)
+                .map(|pair| hash_children(&pair[0], &pair[1]))
+                .collect();
+
             // prepend the nodes of the current level to the front of the array
-            let mut array2= next_level.clone();
-            array2.extend_from_slice(&array.clone());
+            let mut array2 = next_level.clone();
+            array2.extend_from_slice(&array);
             array = array2;
 
             curr_level = next_level;
@@ -61,8 +71,9 @@ impl MerkleTree {
 
         // Create a MerkleTree instance with the root node and the level count.
         MerkleTree {
-            array: array,
-            level_count: level_count,
+            array,
+            level_count,
+            leaf_count: data.len(),
         }
     }
 
@@ -70,17 +81,16 @@ impl MerkleTree {
         self.array[0]
     }
 
-    /// Returns the Merkle Proof of data at index i
+    /// Returns the Merkle Proof of data at index i, from the leaf up to the root
     pub fn proof(&self, index: usize) -> Vec<H256> {
+        assert!(index < self.leaf_count, "no leaf {} in a tree of {} leaves", index, self.leaf_count);
         let mut proof: Vec<H256> = Vec::new();
 
         // 2**(level_count - 1) - 1 is the index of the first node in the bottom level
         let mut i = index + 2usize.pow(self.level_count as u32 - 1) - 1;
-        let mut j = 0;
-        while j < self.level_count - 1 {
-
+        for _ in 0..self.level_count - 1 {
             // get the sibling node
-            if i % 2 == 0 {
+            if i.is_multiple_of(2) {
                 proof.push(self.array[i - 1]);
             } else {
                 proof.push(self.array[i + 1]);
@@ -88,40 +98,32 @@ impl MerkleTree {
 
             // move to the parent node
             i = (i - 1) / 2;
-            j += 1;
         }
         proof
     }
 }
 
 /// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
-/// index of datum and `leaf_size`, the total number of leaves.
+/// index of datum and `leaf_size`, the total number of leaves. The proof must have exactly one
+/// hash per level of a tree of `leaf_size` leaves.
 pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size: usize) -> bool {
-    
-    // convert the index to binary to know either the left or right child is the current node
-    // the binary index is the path from the leaf to the root
-    let mut binary_index: Vec<usize> = Vec::new();
-    let level = (leaf_size as f64).log2().ceil() as usize + 1;
-    let mut i = index + 2usize.pow(level as u32 - 1) - 1;
-    while i > 0 {
-        i -= 1;
-        binary_index.push(i % 2);
-        i /= 2;
+    if index >= leaf_size || proof.len() != depth(leaf_size) {
+        return false;
     }
 
-    // verify the proof by hashing the current node with the sibling node along the path up to the root
-    let mut curr_hash = *datum;
-    for j in 0..proof.len() {
-        if binary_index[j] == 0 {
-            curr_hash = hash_children(&curr_hash, &proof[j]);
+    // the bits of the index, from the lowest, tell whether the current node is a left or a right
+    // child on the path from the leaf to the root
+    let mut curr_hash = hash_leaf(datum);
+    for (level, sibling) in proof.iter().enumerate() {
+        if (index >> level) & 1 == 0 {
+            curr_hash = hash_children(&curr_hash, sibling);
         } else {
-            curr_hash = hash_children(&proof[j], &curr_hash);
+            curr_hash = hash_children(sibling, &curr_hash);
         }
     }
 
     // compare the computed hash with the root hash
     *root == curr_hash
-
 }
 
 #[cfg(test)]
@@ -160,14 +162,15 @@ mod tests {
         let root = merkle_tree.root();
         assert_eq!(
             root,
-            (hex!("6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920")).into()
+            (hex!("60253b9ff3bb53d93bedd4629c764ced1e1ff6520d9bf0ee715a1753a059feae")).into()
         );
-        // "b69566be6e1720872f73651d1851a0eae0060a132cf0f64a0ffaea248de6cba0" is the hash of
-        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        // "a67925cfca2d309c3b85f43f14cc3e0d932f616eac45098acd0b720d01ca2485" is the leaf hash of
+        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d", i.e. the hash of 00
+        // followed by its hash
+        // "e12bdca0d07284b30ce3b2ec0df4c955b26f3b79239cb5bc97629f1a2c5886d1" is the leaf hash of
         // "0101010101010101010101010101010101010101010101010101010101010202"
-        // "6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920" is the hash of
-        // the concatenation of these two hashes "b69..." and "965..."
+        // "60253b9ff3bb53d93bedd4629c764ced1e1ff6520d9bf0ee715a1753a059feae" is the hash of
+        // 01 followed by the concatenation of these two hashes "a67..." and "e12..."
         // notice that the order of these two matters
     }
 
@@ -177,9 +180,9 @@ mod tests {
         let merkle_tree = MerkleTree::new(&input_data);
         let proof = merkle_tree.proof(0);
         assert_eq!(proof,
-                   vec![hex!("965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f").into()]
+                   vec![hex!("e12bdca0d07284b30ce3b2ec0df4c955b26f3b79239cb5bc97629f1a2c5886d1").into()]
         );
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        // "e12bdca0d07284b30ce3b2ec0df4c955b26f3b79239cb5bc97629f1a2c5886d1" is the leaf hash of
         // "0101010101010101010101010101010101010101010101010101010101010202"
     }
 
@@ -191,14 +194,14 @@ mod tests {
   
         // We accept the proof in either the top-down or bottom-up order; you should stick to either of them.
         let expected_proof_bottom_up: Vec<H256> = vec![
-            (hex!("c8c37c89fcc6ee7f5e8237d2b7ed8c17640c154f8d7751c774719b2b82040c76")).into(),
-            (hex!("bada70a695501195fb5ad950a5a41c02c0f9c449a918937267710a0425151b77")).into(),
-            (hex!("1e28fb71415f259bd4b0b3b98d67a1240b4f3bed5923aa222c5fdbd97c8fb002")).into(),
+            (hex!("24153d02c842f95404c3133ef33be3164ed19412bacf59361b447af2be3fdf87")).into(),
+            (hex!("74312503d21014ad3b25e2ce24683eb02bc66660195fd470212b437c563cee98")).into(),
+            (hex!("3b992847faed0bf98f70dc79b3e2c6b3ed75ab6d011241b3fb65e16836583119")).into(),
         ];
         let expected_proof_top_down: Vec<H256> = vec![
-            (hex!("1e28fb71415f259bd4b0b3b98d67a1240b4f3bed5923aa222c5fdbd97c8fb002")).into(),  
-            (hex!("bada70a695501195fb5ad950a5a41c02c0f9c449a918937267710a0425151b77")).into(),
-            (hex!("c8c37c89fcc6ee7f5e8237d2b7ed8c17640c154f8d7751c774719b2b82040c76")).into(),
+            (hex!("3b992847faed0bf98f70dc79b3e2c6b3ed75ab6d011241b3fb65e16836583119")).into(),
+            (hex!("74312503d21014ad3b25e2ce24683eb02bc66660195fd470212b437c563cee98")).into(),
+            (hex!("24153d02c842f95404c3133ef33be3164ed19412bacf59361b447af2be3fdf87")).into(),
         ];
         assert!(proof == expected_proof_bottom_up || proof == expected_proof_top_down);
     }
@@ -211,4 +214,63 @@ mod tests {
         assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof, 0, input_data.len()));
     }
 
+    #[test]
+    fn verifying_every_size() {
+        for size in 1..=9 {
+            let input_data: Vec<H256> = (0..size).map(|i| [i as u8; 32].into()).collect();
+            let merkle_tree = MerkleTree::new(&input_data);
+            let root = merkle_tree.root();
+            for (index, datum) in input_data.iter().enumerate() {
+                let proof = merkle_tree.proof(index);
+                assert!(verify(&root, &datum.hash(), &proof, index, size), "{} of {}", index, size);
+                // the proof is bound to the position and the size of the tree
+                assert!(!verify(&root, &datum.hash(), &proof, index ^ 1, size));
+                assert!(!verify(&root, &datum.hash(), &proof, index, size * 2));
+            }
+            // the padding after an odd number of leaves cannot be proven, even with the leaf
+            // count rounded up
+            if size % 2 == 1 && size > 1 {
+                let mut proof = merkle_tree.proof(size - 1);
+                proof[0] = hash_leaf(&input_data[size - 1].hash());
+                assert!(!verify(&root, &H256::default(), &proof, size, size + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree() {
+        let merkle_tree = MerkleTree::new(&Vec::<H256>::new());
+        assert_eq!(merkle_tree.root(), H256::default());
+        assert!(!verify(&merkle_tree.root(), &H256::default(), &[], 0, 0));
+    }
+
+    #[test]
+    fn duplicated_last_leaf() {
+        // CVE-2012-2459: with the last leaf duplicated as padding, [a, b, c] and [a, b, c, c]
+        // had the same root, so a valid block could be mutated into an invalid one with the same
+        // hash
+        let three: Vec<H256> = gen_merkle_tree_large!().into_iter().take(3).collect();
+        let mut four = three.clone();
+        four.push(three[2]);
+        assert_ne!(MerkleTree::new(&three).root(), MerkleTree::new(&four).root());
+
+        let mut five: Vec<H256> = gen_merkle_tree_large!().into_iter().take(5).collect();
+        let root = MerkleTree::new(&five).root();
+        five.extend_from_slice(&[five[4]; 3]);
+        assert_ne!(MerkleTree::new(&five).root(), root);
+    }
+
+    #[test]
+    fn internal_nodes_are_not_leaves() {
+        // the root of [a, b, c, d] is the hash of the two internal nodes; they must not verify
+        // as the leaves of a tree of two
+        let input_data: Vec<H256> = gen_merkle_tree_large!().into_iter().take(4).collect();
+        let merkle_tree = MerkleTree::new(&input_data);
+        let (left, right) = (merkle_tree.array[1], merkle_tree.array[2]);
+        assert!(!verify(&merkle_tree.root(), &left, &[right], 0, 2));
+        // nor can a leaf be presented as a node of a deeper tree
+        let proof = merkle_tree.proof(0);
+        assert!(!verify(&merkle_tree.root(), &input_data[0].hash(), &proof[1..], 0, 2));
+    }
+
 }
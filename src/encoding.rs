@@ -0,0 +1,359 @@
+use crate::address::H160;
+use crate::crypto::hash::H256;
+use std::convert::TryFrom;
+
+// The canonical binary encoding of consensus objects. Block and transaction hashes and signatures
+// are computed over it, so the encoding of a version must never change; a change of the layout
+// gets a new version byte instead.
+//
+// An encoded object is the version byte `ENCODING_VERSION` followed by the object's fields in
+// order, with nested objects encoded without a version byte of their own:
+// - u8, u32, u64 and u128 are fixed width and little endian
+// - H256 and H160 are their 32 and 20 bytes
+// - byte strings and lists are their number of items as a u32, followed by the items
+// - an option is 0 if it is empty, or 1 followed by the value
+// - an enum is a u8 tag for the variant, followed by the variant's fields
+//
+// The consensus objects are laid out as follows:
+// - RawTransaction: from_addr H160, to_addr H160, value u64, nonce u32, chain_id H256,
+//   expiry_height option of u64
+// - Witness: tag 0 with pub_key bytes and signature bytes, or tag 1 with a MultisigPolicy and a
+//   list of KeySignatures
+// - MultisigPolicy: threshold u8, pub_keys list of bytes
+// - KeySignature: index u8, signature bytes
+// - SignedTransaction: raw RawTransaction, witness Witness
+// - Header: parent H256, nonce u32, difficulty H256, timestamp u128, merkle_root H256
+// - Block: header Header, transactions list of SignedTransaction
+//
+// Decoding rejects unknown versions and tags, truncated input and trailing bytes, so that every
+// object has exactly one encoding.
+
+/// The version of the encoding, the first byte of every encoded object
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Reasons bytes are not the encoding of an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The encoding has another version than `ENCODING_VERSION`
+    UnsupportedVersion(u8),
+    /// The input ends in the middle of the object
+    UnexpectedEnd,
+    /// An option or enum has an unknown tag
+    InvalidTag { what: &'static str, tag: u8 },
+    /// Bytes are left over after the object
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported encoding version {}", version),
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag { what, tag } => write!(f, "invalid {} tag {}", what, tag),
+            DecodeError::TrailingBytes(count) => write!(f, "{} trailing bytes", count),
+        }
+    }
+}
+
+/// The input of `Decode`, consumed from the front.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    /// Consume the next `count` bytes.
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if count > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// The number of bytes not consumed yet
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+/// An object with a canonical encoding.
+pub trait Encode {
+    /// Append the fields of the object to `out`, without the version byte.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// The encoding of the object, starting with the version byte.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![ENCODING_VERSION];
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+/// An object that can be decoded from its canonical encoding.
+pub trait Decode: Sized {
+    /// Read the fields of the object, without the version byte.
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError>;
+
+    /// Decode an object from `bytes`, which must be exactly its encoding.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let version = u8::decode_from(&mut reader)?;
+        if version != ENCODING_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let object = Self::decode_from(&mut reader)?;
+        match reader.remaining() {
+            0 => Ok(object),
+            count => Err(DecodeError::TrailingBytes(count)),
+        }
+    }
+}
+
+macro_rules! integer_encoding {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $t {
+                fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+                    let mut bytes = [0u8; std::mem::size_of::<$t>()];
+                    bytes.copy_from_slice(reader.take(std::mem::size_of::<$t>())?);
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+integer_encoding!(u8, u32, u64, u128);
+
+impl Encode for H256 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Decode for H256 {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(reader.take(32)?);
+        Ok(bytes.into())
+    }
+}
+
+impl Encode for H160 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Decode for H160 {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 20];
+        bytes.copy_from_slice(reader.take(20)?);
+        Ok(bytes.into())
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        u32::try_from(self.len()).expect("list too long to encode").encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let count = u32::decode_from(reader)? as usize;
+        // every item takes at least a byte, so a bogus count cannot make us allocate much more
+        // than the input
+        let mut items = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            items.push(T::decode_from(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match u8::decode_from(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(reader)?)),
+            tag => Err(DecodeError::InvalidTag { what: "option", tag }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, Content, Header};
+    use crate::crypto::hash::Hashable;
+    use crate::transaction::{MultisigPolicy, RawTransaction, SignedTransaction};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn raw_transaction() -> RawTransaction {
+        RawTransaction {
+            from_addr: [1u8; 20].into(),
+            to_addr: [2u8; 20].into(),
+            value: 1000,
+            nonce: 7,
+            chain_id: [3u8; 32].into(),
+            expiry_height: Some(10),
+        }
+    }
+
+    fn header() -> Header {
+        Header {
+            parent: [4u8; 32].into(),
+            nonce: 42,
+            difficulty: [5u8; 32].into(),
+            timestamp: 1_600_000_000_000,
+            merkle_root: [6u8; 32].into(),
+        }
+    }
+
+    fn key(byte: u8) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(0x0102_0304u32.encode(), vec![1, 4, 3, 2, 1]);
+        assert_eq!(vec![0xabu8, 0xcd].encode(), vec![1, 2, 0, 0, 0, 0xab, 0xcd]);
+        assert_eq!(Some(3u8).encode(), vec![1, 1, 3]);
+        assert_eq!(None::<u8>.encode(), vec![1, 0]);
+        assert_eq!(Option::<u64>::decode(&[1, 1, 9, 0, 0, 0, 0, 0, 0, 0]), Ok(Some(9)));
+
+        assert_eq!(u32::decode(&[2, 0, 0, 0, 0]), Err(DecodeError::UnsupportedVersion(2)));
+        assert_eq!(u32::decode(&[1, 0, 0, 0]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(u32::decode(&[1, 0, 0, 0, 0, 0]), Err(DecodeError::TrailingBytes(1)));
+        assert_eq!(Option::<u8>::decode(&[1, 2, 0]), Err(DecodeError::InvalidTag { what: "option", tag: 2 }));
+        // a huge length prefix fails on the missing items instead of allocating them
+        assert_eq!(Vec::<u64>::decode(&[1, 0xff, 0xff, 0xff, 0xff, 0]), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn raw_transaction_vector() {
+        let raw = raw_transaction();
+        let expected = [
+            "01",
+            &"01".repeat(20),
+            &"02".repeat(20),
+            "e803000000000000",
+            "07000000",
+            &"03".repeat(32),
+            "010a00000000000000",
+        ]
+        .concat();
+        assert_eq!(hex::encode(raw.encode()), expected);
+        let decoded = RawTransaction::decode(&raw.encode()).unwrap();
+        assert_eq!(decoded.encode(), raw.encode());
+    }
+
+    #[test]
+    fn header_vector() {
+        let expected = [
+            "01",
+            &"04".repeat(32),
+            "2a000000",
+            &"05".repeat(32),
+            "00806e87740100000000000000000000",
+            &"06".repeat(32),
+        ]
+        .concat();
+        assert_eq!(hex::encode(header().encode()), expected);
+        assert_eq!(Header::decode(&header().encode()).unwrap().encode(), header().encode());
+        assert_eq!(
+            header().hash().to_string(),
+            "b363ee5b5cb1d3813039804293e49b89dcbdbd5fe8d97e4d9536ddd4424c568a"
+        );
+    }
+
+    #[test]
+    fn signed_transaction_vectors() {
+        // Ed25519 signatures are deterministic, so the whole transaction is fixed. The signature
+        // is over `SIGNING_TAG` followed by the encoding of the raw transaction.
+        let single = SignedTransaction::from_raw(raw_transaction(), &key(1));
+        let encoded = single.encode();
+        assert_eq!(&encoded[..94], &raw_transaction().encode()[..]);
+        assert_eq!(
+            hex::encode(&encoded[94..]),
+            [
+                "00",
+                "20000000",
+                "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c",
+                "40000000",
+                "0a2c2b9ad455101cfd7786f2c712e960201a93272da344465756bd51b141ea61",
+                "453e4405a32d88ceae8592befd90da8ac7cad25200b535f8725e6856d0b7b902",
+            ]
+            .concat()
+        );
+        assert_eq!(
+            single.hash().to_string(),
+            "1865c2a81928e49dc1144c281bbb0a17139ab9a444a85ad9c0f06b6c8d6372d4"
+        );
+        assert_eq!(SignedTransaction::decode(&encoded).unwrap().hash(), single.hash());
+
+        let pub_keys = vec![key(1).public_key().as_ref().to_vec(), key(2).public_key().as_ref().to_vec()];
+        let policy = MultisigPolicy::new(2, pub_keys).unwrap();
+        let mut raw = raw_transaction();
+        raw.from_addr = policy.address();
+        let multisig = SignedTransaction::from_multisig(raw, policy, &[&key(2), &key(1)]);
+        let encoded = multisig.encode();
+        // tag, threshold, two keys, then the two signatures in key order
+        assert_eq!(hex::encode(&encoded[94..100]), "010202000000");
+        assert_eq!(
+            multisig.hash().to_string(),
+            "e5fd539539d4dc8622051548daa9bec1d132f7ceb77827203d1fc09a11c28438"
+        );
+        let decoded = SignedTransaction::decode(&encoded).unwrap();
+        assert!(decoded.verify_signature());
+        assert_eq!(decoded.hash(), multisig.hash());
+
+        let mut bad_tag = encoded.clone();
+        bad_tag[94] = 2;
+        assert_eq!(
+            SignedTransaction::decode(&bad_tag).unwrap_err(),
+            DecodeError::InvalidTag { what: "witness", tag: 2 }
+        );
+    }
+
+    #[test]
+    fn block_vector() {
+        let transactions = vec![SignedTransaction::from_raw(raw_transaction(), &key(1))];
+        let block = Block { header: header(), content: Content { transactions } };
+        let encoded = block.encode();
+        assert_eq!(&encoded[..117], &header().encode()[..]);
+        assert_eq!(hex::encode(&encoded[117..121]), "01000000");
+        // the block is identified by its header alone
+        assert_eq!(block.hash(), header().hash());
+        assert_eq!(Block::decode(&encoded).unwrap().hash(), block.hash());
+        let genesis = ["01", &"00".repeat(36), "00", &"ff".repeat(31), &"00".repeat(48), "00000000"].concat();
+        assert_eq!(hex::encode(Block::genesis().encode()), genesis);
+    }
+}
@@ -6,28 +6,32 @@ pub mod api;
 pub mod block;
 pub mod blockchain;
 pub mod crypto;
+pub mod encoding;
 pub mod miner;
 pub mod network;
 pub mod transaction;
 pub mod address;
+pub mod bech32;
 pub mod mempool;
+pub mod spv;
+pub mod metrics;
 pub mod transaction_generator;
+pub mod wallet;
 
-use address::get_deterministic_keypair;
+use address::ico_keypair;
 use clap::clap_app;
 use crossbeam::channel;
-use crypto::key_pair;
 use log::debug;
-use log::{error, info};
-use api::Server as ApiServer;
-use network::{server, worker};
+use log::{error, warn};
+use api::auth::{Auth, Role};
+use api::{Node as ApiNode, Server as ApiServer};
+use network::{connector, inventory, keepalive, server, worker};
 use transaction_generator::TransactionGenerator;
 use std::net;
 use std::process;
-use std::thread;
-use std::time;
 use std::sync::{Arc, Mutex};
 use blockchain::Blockchain;
+use wallet::Wallet;
 
 fn main() {
     // parse command line arguments
@@ -37,8 +41,32 @@ fn main() {
      (@arg verbose: -v ... "Increases the verbosity of logging")
      (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
      (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg public_api_addr: --("public-api") [ADDR] "Sets the IP address and the port of an additional API server with only the read-only endpoints")
+     (@arg api_token: --("api-token") [TOKEN] "Sets the bearer token required by the mutating API endpoints")
+     (@arg api_token_file: --("api-token-file") [FILE] "Reads the token required by the mutating API endpoints from a file")
+     (@arg api_read_token: --("api-read-token") [TOKEN] "Sets the bearer token required by the read-only API endpoints")
+     (@arg api_read_token_file: --("api-read-token-file") [FILE] "Reads the token required by the read-only API endpoints from a file")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg genesis_seed: --("genesis-seed") [PHRASE] "Sets the seed phrase from which the ICO accounts are derived; all nodes of a network must use the same one")
+     (@arg genesis_seed_file: --("genesis-seed-file") [FILE] "Reads the seed phrase from which the ICO accounts are derived from a file")
+     (@arg wallet: --wallet [FILE] +global "Sets the encrypted keystore file of the wallet")
+     (@arg wallet_passphrase: --("wallet-passphrase") [PASSPHRASE] +global "Sets the passphrase of the wallet keystore")
+     (@arg wallet_passphrase_file: --("wallet-passphrase-file") [FILE] +global "Reads the passphrase of the wallet keystore from a file")
+     (@subcommand wallet =>
+      (about: "Manages the keys in the wallet keystore without starting the node")
+      (@setting SubcommandRequiredElseHelp)
+      (@subcommand list => (about: "Lists the addresses of the keys"))
+      (@subcommand generate => (about: "Generates a new key and prints its address"))
+      (@subcommand import => (about: "Imports a key and prints its address")
+       (@arg SEED: +required "Hex-encoded 32-byte Ed25519 seed"))
+      (@subcommand derive => (about: "Imports the key at a derivation path of a seed phrase and prints its address")
+       (@arg PHRASE: +required "Seed phrase")
+       (@arg PATH: +required "Derivation path with hardened indices, like m/0'/1'")
+       (@arg passphrase: --passphrase [PASSPHRASE] "Sets the passphrase of the seed phrase"))
+      (@subcommand export => (about: "Prints the seed of a key")
+       (@arg ADDRESS: +required "Address of the key"))
+     )
     )
     .get_matches();
 
@@ -46,6 +74,26 @@ fn main() {
     let verbosity = matches.occurrences_of("verbose") as usize;
     stderrlog::new().verbosity(verbosity).init().unwrap();
 
+    // run wallet commands offline
+    if let Some(wallet_matches) = matches.subcommand_matches("wallet") {
+        let mut wallet = open_wallet(&matches).unwrap_or_else(|| {
+            error!("The wallet command needs a keystore file, set it with --wallet");
+            process::exit(1);
+        });
+        wallet_command(&mut wallet, wallet_matches).unwrap_or_else(|e| {
+            error!("{}", e);
+            process::exit(1);
+        });
+        return;
+    }
+
+    // open the wallet
+    let wallet = open_wallet(&matches).unwrap_or_else(|| {
+        warn!("No wallet keystore set, keys added through the API are lost on exit");
+        Wallet::new()
+    });
+    let wallet = Arc::new(Mutex::new(wallet));
+
     // parse p2p server address
     let p2p_addr = matches
         .value_of("peer_addr")
@@ -66,6 +114,29 @@ fn main() {
             process::exit(1);
         });
 
+    // parse public api server address
+    let public_api_addr = matches.value_of("public_api_addr").map(|addr| {
+        addr.parse::<net::SocketAddr>().unwrap_or_else(|e| {
+            error!("Error parsing public API server address: {}", e);
+            process::exit(1);
+        })
+    });
+
+    // read api tokens
+    let auth = Arc::new(Auth::new(
+        secret(&matches, "api_token", "api_token_file", "API token"),
+        secret(&matches, "api_read_token", "api_read_token_file", "API token"),
+    ));
+    if auth.admin_is_public() {
+        warn!("No API token set, anyone who can reach {} can use the mutating API endpoints", api_addr);
+    }
+
+    // derive the genesis seed
+    let genesis_seed = match secret(&matches, "genesis_seed", "genesis_seed_file", "genesis seed") {
+        Some(phrase) => crypto::hd::seed_from_phrase(&phrase, ""),
+        None => address::default_genesis_seed(),
+    };
+
     // create channels between server and worker
     let (msg_tx, msg_rx) = channel::unbounded();
 
@@ -73,6 +144,14 @@ fn main() {
     let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
     server_ctx.start().unwrap();
 
+    // start pinging peers to keep connections alive and measure latency
+    let (keepalive_ctx, keepalive) = keepalive::new(&server);
+    keepalive_ctx.start();
+
+    // start announcing new blocks and transactions to peers
+    let (inventory_ctx, inventory) = inventory::new(&server);
+    inventory_ctx.start();
+
     // start the worker
     let p2p_workers = matches
         .value_of("p2p_workers")
@@ -83,21 +162,23 @@ fn main() {
             process::exit(1);
         });
     // start the miner
-    let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+    let blockchain = Arc::new(Mutex::new(Blockchain::with_genesis_seed(&genesis_seed)));
     let mempool = Arc::new(Mutex::new(mempool::Mempool::new()));
     let worker_ctx = worker::new(
         p2p_workers,
         msg_rx,
         &server,
+        &keepalive,
+        &inventory,
         blockchain.clone(),
         mempool.clone(),
     );
     worker_ctx.start();
 
     // start the transaction generator
-    let controlled_key_pair = get_deterministic_keypair(0);  // get one account from ICO (from nonce 0 to 9)
+    let controlled_key_pair = ico_keypair(&genesis_seed, 0);  // get one account from ICO (index 0 to 9)
     let transaction_generator = TransactionGenerator::new(
-        &server, &mempool, &blockchain, controlled_key_pair
+        &inventory, &mempool, &blockchain, controlled_key_pair
     );
 
     debug!("Transaction generator started");
@@ -105,52 +186,117 @@ fn main() {
 
     // start the miner
     let (miner_ctx, miner) = miner::new(
-        &server, &blockchain, &mempool
+        &inventory, &blockchain, &mempool
     );
     miner_ctx.start();
 
-    // connect to known peers
+    // connect to known peers, and keep reconnecting to them whenever they drop
+    let (connector_ctx, connector) = connector::new(&server);
+    connector_ctx.start();
     if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
-        let server = server.clone();
-        thread::spawn(move || {
-            for peer in known_peers {
-                loop {
-                    let addr = match peer.parse::<net::SocketAddr>() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("Error parsing peer address {}: {}", &peer, e);
-                            break;
-                        }
-                    };
-                    match server.connect(addr) {
-                        Ok(_) => {
-                            info!("Connected to outgoing peer {}", &addr);
-                            break;
-                        }
-                        Err(e) => {
-                            error!(
-                                "Error connecting to peer {}, retrying in one second: {}",
-                                addr, e
-                            );
-                            thread::sleep(time::Duration::from_millis(1000));
-                            continue;
-                        }
-                    }
-                }
+        for peer in known_peers {
+            match peer.parse::<net::SocketAddr>() {
+                Ok(addr) => connector.add_peer(addr),
+                Err(e) => error!("Error parsing peer address {}: {}", peer, e),
             }
-        });
+        }
     }
 
 
     // start the API server
-    ApiServer::start(
-        api_addr,
-        &miner,
-        &server,
-    );
+    let api_node = ApiNode {
+        miner,
+        network: server,
+        connector,
+        keepalive,
+        inventory,
+        blockchain,
+        mempool,
+        wallet,
+    };
+    if let Some(public_api_addr) = public_api_addr {
+        ApiServer::start(public_api_addr, api_node.clone(), &auth, Role::ReadOnly);
+    }
+    ApiServer::start(api_addr, api_node, &auth, Role::Admin);
 
     loop {
         std::thread::park();
     }
 }
+
+/// Read a secret given either directly or as the path of a file containing it.
+fn secret(matches: &clap::ArgMatches, arg: &str, file_arg: &str, what: &str) -> Option<String> {
+    match (matches.value_of(arg), matches.value_of(file_arg)) {
+        (Some(_), Some(_)) => {
+            error!("Only one of --{} and --{} may be given", arg.replace('_', "-"), file_arg.replace('_', "-"));
+            process::exit(1);
+        }
+        (Some(secret), None) => Some(secret.to_string()),
+        (None, Some(path)) => {
+            let secret = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                error!("Error reading {} file {}: {}", what, path, e);
+                process::exit(1);
+            });
+            let secret = secret.trim();
+            if secret.is_empty() {
+                error!("{} file {} is empty", what, path);
+                process::exit(1);
+            }
+            Some(secret.to_string())
+        }
+        (None, None) => None,
+    }
+}
+
+/// Open the wallet keystore given by --wallet, if any.
+fn open_wallet(matches: &clap::ArgMatches) -> Option<Wallet> {
+    let path = matches.value_of("wallet")?;
+    let passphrase = secret(matches, "wallet_passphrase", "wallet_passphrase_file", "wallet passphrase")
+        .unwrap_or_else(|| {
+            error!("The wallet keystore needs a passphrase, set it with --wallet-passphrase or --wallet-passphrase-file");
+            process::exit(1);
+        });
+    let wallet = Wallet::open(std::path::Path::new(path), &passphrase).unwrap_or_else(|e| {
+        error!("Error opening wallet {}: {}", path, e);
+        process::exit(1);
+    });
+    Some(wallet)
+}
+
+/// Run a `wallet` subcommand and print its result.
+fn wallet_command(wallet: &mut Wallet, matches: &clap::ArgMatches) -> Result<(), String> {
+    match matches.subcommand() {
+        ("list", _) => {
+            for address in wallet.addresses() {
+                println!("{}", address);
+            }
+        }
+        ("generate", _) => println!("{}", wallet.generate().map_err(|e| e.to_string())?),
+        ("import", Some(args)) => {
+            let seed = wallet::parse_seed(args.value_of("SEED").unwrap()).map_err(|e| e.to_string())?;
+            println!("{}", wallet.import(seed).map_err(|e| e.to_string())?);
+        }
+        ("derive", Some(args)) => {
+            let path = args
+                .value_of("PATH")
+                .unwrap()
+                .parse::<crypto::hd::DerivationPath>()
+                .map_err(|e| format!("Error parsing derivation path: {}", e))?;
+            let passphrase = args.value_of("passphrase").unwrap_or("");
+            let address = wallet
+                .import_derived(args.value_of("PHRASE").unwrap(), passphrase, &path)
+                .map_err(|e| e.to_string())?;
+            println!("{}", address);
+        }
+        ("export", Some(args)) => {
+            let address = args
+                .value_of("ADDRESS")
+                .unwrap()
+                .parse::<address::H160>()
+                .map_err(|e| format!("Error parsing address: {}", e))?;
+            println!("{}", hex::encode(wallet.export(&address).map_err(|e| e.to_string())?));
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
@@ -1,22 +1,208 @@
 use log::warn;
+use serde::Serialize;
 
-use crate::{address::H160, blockchain::State, transaction::SignedTransaction as Transaction};
+use crate::{address::H160, block::short_id, blockchain::State, transaction::SignedTransaction as Transaction};
 use std::collections::HashMap;
 use crate::crypto::hash::{H256, Hashable};
+use crate::metrics::metrics;
+use crossbeam::channel::{self, Receiver, Sender};
+use std::sync::atomic::Ordering;
+
+/// Why a transaction is rejected from the mempool.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum TransactionError {
+    /// The transaction is already in the mempool
+    AlreadyKnown,
+    /// The signature does not verify against `pub_key`, or a multisig transaction does not carry
+    /// exactly `threshold` valid signatures
+    InvalidSignature,
+    /// `from_addr` is not the address of `pub_key` or of the multisig key set
+    AddressMismatch {
+        #[serde(serialize_with = "serialize_display")]
+        from_addr: H160,
+        #[serde(serialize_with = "serialize_display")]
+        pub_key_addr: H160,
+    },
+    /// The nonce is not one more than the sender's account nonce
+    BadNonce { expected: u32, got: u32 },
+    /// The sender cannot afford the transferred value
+    InsufficientBalance { balance: u64, value: u64 },
+    /// The transaction is signed for another chain
+    WrongChain {
+        #[serde(serialize_with = "serialize_display")]
+        expected: H256,
+        #[serde(serialize_with = "serialize_display")]
+        got: H256,
+    },
+    /// The next block is above the transaction's expiry height
+    Expired { expiry_height: u64, height: u64 },
+}
+
+pub(crate) fn serialize_display<T: std::fmt::Display, S: serde::Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionError::AlreadyKnown => write!(f, "transaction already in mempool"),
+            TransactionError::InvalidSignature => write!(f, "failed to verify signature"),
+            TransactionError::AddressMismatch { from_addr, pub_key_addr } => write!(
+                f,
+                "from_addr {} does not match the address of pub_key {}",
+                from_addr, pub_key_addr
+            ),
+            TransactionError::BadNonce { expected, got } => {
+                write!(f, "nonce should be {} but is {}", expected, got)
+            }
+            TransactionError::InsufficientBalance { balance, value } => write!(
+                f,
+                "sender balance {} is less than the value {}",
+                balance, value
+            ),
+            TransactionError::WrongChain { expected, got } => {
+                write!(f, "transaction is signed for chain {} instead of {}", got, expected)
+            }
+            TransactionError::Expired { expiry_height, height } => write!(
+                f,
+                "transaction expired at height {} but the next block has height {}",
+                expiry_height, height
+            ),
+        }
+    }
+}
+
+/// Check a transaction against `state`: the signature, the sender address, the nonce and the
+/// balance. Senders without an account have nonce 0 and balance 0.
+pub fn validate(state: &State, transaction: &Transaction) -> Result<(), TransactionError> {
+    // signature validity check
+    if !transaction.verify_signature() {
+        return Err(TransactionError::InvalidSignature);
+    }
+    validate_verified(state, transaction)
+}
+
+/// `validate` for a transaction whose signature was already checked, e.g. by `verify_batch`.
+pub fn validate_verified(state: &State, transaction: &Transaction) -> Result<(), TransactionError> {
+    let account = state.get(&transaction.signer()).cloned().unwrap_or((0, 0));
+    validate_account(state, account, transaction)
+}
+
+/// Check a transaction whose signature was already checked against `state`, with `account` as the
+/// nonce and balance of the sender.
+fn validate_account(state: &State, account: (u32, u64), transaction: &Transaction) -> Result<(), TransactionError> {
+    // replay protection: the transaction must be signed for this chain and not have expired by the
+    // next block
+    if transaction.raw.chain_id != state.chain_id() {
+        return Err(TransactionError::WrongChain {
+            expected: state.chain_id(),
+            got: transaction.raw.chain_id,
+        });
+    }
+    if let Some(expiry_height) = transaction.raw.expiry_height {
+        if expiry_height <= state.height() {
+            return Err(TransactionError::Expired { expiry_height, height: state.height() + 1 });
+        }
+    }
+    // the public key, or the multisig key set, must match the owner's address of the withdrawing account
+    let sender = transaction.signer();
+    if transaction.raw.from_addr != sender {
+        return Err(TransactionError::AddressMismatch {
+            from_addr: transaction.raw.from_addr,
+            pub_key_addr: sender,
+        });
+    }
+    // double spend checks
+    let (nonce, balance) = account;
+    if nonce + 1 != transaction.raw.nonce {
+        return Err(TransactionError::BadNonce {
+            expected: nonce + 1,
+            got: transaction.raw.nonce,
+        });
+    }
+    if balance < transaction.raw.value {
+        return Err(TransactionError::InsufficientBalance {
+            balance,
+            value: transaction.raw.value,
+        });
+    }
+    Ok(())
+}
+
+/// Why a transaction left the mempool.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// It was included in a block
+    Included,
+    /// It can no longer be mined on the tip
+    Invalid,
+    /// It was dropped on request
+    Dropped,
+}
+
+/// Changes to the mempool, delivered to subscribers.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MempoolEvent {
+    TransactionAccepted {
+        #[serde(serialize_with = "serialize_display")]
+        hash: H256,
+    },
+    TransactionEvicted {
+        #[serde(serialize_with = "serialize_display")]
+        hash: H256,
+        reason: EvictionReason,
+    },
+}
+
+impl MempoolEvent {
+    /// The event type, as used in the serialized `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            MempoolEvent::TransactionAccepted { .. } => "transaction_accepted",
+            MempoolEvent::TransactionEvicted { .. } => "transaction_evicted",
+        }
+    }
+}
 
 /// Store all the received valid transactions which have not been included in the blockchain yet.
 pub struct Mempool {
     // TODO Optional: you may use other data structures if you wish.
     pub hash_to_transaction: HashMap<H256, Transaction>,
+    subscribers: Vec<Sender<MempoolEvent>>,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Mempool {
     pub fn new() -> Self {
         Mempool {
             hash_to_transaction: HashMap::new(),
+            subscribers: vec![],
         }
     }
 
+    /// Subscribe to mempool events
+    pub fn subscribe(&mut self) -> Receiver<MempoolEvent> {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn publish(&mut self, event: MempoolEvent) {
+        metrics()
+            .mempool_transactions
+            .store(self.hash_to_transaction.len() as u64, Ordering::Relaxed);
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+    }
+
     /// Get a transaction from the mempool by hash (or `None` if it does not exist)
     pub fn get_transaction(&self, hash: &H256) -> Option<&Transaction> {
         self.hash_to_transaction.get(hash)
@@ -26,14 +212,16 @@ impl Mempool {
     pub fn insert(&mut self, transaction: Transaction) {
         // (Make sure you have implemented the `Hashable` trait for `SignedTransaction`, or there will be an error):
         let hash = transaction.hash();
-        self.hash_to_transaction.insert(hash, transaction);
+        if self.hash_to_transaction.insert(hash, transaction).is_none() {
+            self.publish(MempoolEvent::TransactionAccepted { hash });
+        }
     }
 
     /// Remove a random transaction from the mempool and return it (or `None` if it is empty)
     pub fn pop(&mut self) -> Option<Transaction> {
         let hash = self.hash_to_transaction.keys().next().cloned();
         if let Some(hash) = hash {
-            self.hash_to_transaction.remove(&hash)
+            self.remove(&hash, EvictionReason::Dropped)
         } else {
             None
         }
@@ -49,9 +237,14 @@ impl Mempool {
         hashes.iter().filter_map(|hash| self.get_transaction(hash).cloned()).collect()
     }
 
+    // Index the transactions in the mempool by their short IDs in the block `block_hash`
+    pub fn short_id_index(&self, block_hash: &H256) -> HashMap<u64, H256> {
+        self.hash_to_transaction.keys().map(|hash| (short_id(block_hash, hash), *hash)).collect()
+    }
+
     // Get a number of transactions from the mempool
     pub fn get_n_transactions(&self, number: usize) -> Vec<Transaction> {
-        self.hash_to_transaction.values().cloned().take(number).collect()
+        self.hash_to_transaction.values().take(number).cloned().collect()
     }
 
     pub fn get_valid_transactions(&self, state: &State) -> Vec<Transaction> {
@@ -65,10 +258,12 @@ impl Mempool {
         valid_transactions
     }
 
+    /// Transactions that can no longer be mined on top of `state`, even after the pending
+    /// transactions of their sender.
     pub fn get_invalid_transactions(&self, state: &State) -> Vec<Transaction> {
         let mut invalid_transactions = Vec::new();
         for transaction in self.hash_to_transaction.values() {
-            if !self.is_valid(state.clone(), transaction.clone()) {
+            if self.validate_pending(state, transaction).is_err() {
                 invalid_transactions.push(transaction.clone());
             }
         }
@@ -76,37 +271,63 @@ impl Mempool {
     }
 
     pub fn is_valid(&self, state: State, transaction: Transaction) -> bool {
-        // validity check
-        if !transaction.verify_signature() {
-            warn!("Invalid transaction detected in : Failed to verify signature");
-            return false
+        match validate(&state, &transaction) {
+            Ok(()) => true,
+            // stale nonces are expected after every new block, don't flood the log with them
+            Err(TransactionError::BadNonce { .. }) => false,
+            Err(e) => {
+                warn!("Invalid transaction detected: {}", e);
+                false
+            }
         }
+    }
 
-        if transaction.raw.from_addr != H160::from_pubkey(&transaction.pub_key) {
-            warn!("Invalid transaction detected: Failed to match from_addr: {:?} with address of pub_key: {:?}", transaction.raw.from_addr, H160::from_pubkey(&transaction.pub_key));
-            return false
+    /// The nonce and balance of `address` after its pending transactions with a nonce below
+    /// `nonce`, applied in nonce order on top of `state`. Transactions that do not continue the
+    /// nonce sequence, or that overspend, are not applied.
+    pub fn pending_account(&self, state: &State, address: &H160, nonce: u32) -> (u32, u64) {
+        let mut pending: Vec<&Transaction> = self
+            .hash_to_transaction
+            .values()
+            .filter(|tx| tx.raw.from_addr == *address && tx.raw.nonce < nonce)
+            .collect();
+        pending.sort_by_key(|tx| tx.raw.nonce);
+        let (mut account_nonce, mut balance) = state.get(address).cloned().unwrap_or((0, 0));
+        for tx in pending {
+            if tx.raw.nonce == account_nonce + 1 && tx.raw.value <= balance {
+                account_nonce += 1;
+                balance -= tx.raw.value;
+            }
         }
+        (account_nonce, balance)
+    }
 
-        let sender = H160::from_pubkey(&transaction.pub_key);
-        // get the sender's nonce and balance
-        let (sender_account_nonce, sender_account_balance) = state.get(&sender).unwrap();
-        // check if the nonce (sender) is correct
-        if sender_account_nonce + 1 != transaction.raw.nonce {  // TODO: sender_account_nonce is always 0, the nonce of sender account at the tip of the blockchain => tip never changes?
-            // warn!("Invalid transaction detected: tx's nonce: {:?} should be 1 more than sender_account_nonce: {:?}", transaction.raw.nonce, sender_account_nonce);
-            return false
-        }
-        // check if the balance is enough
-        if sender_account_balance < &transaction.raw.value {
-            warn!("Invalid transaction detected: Sender account doesn't have enough balance with value: {:?} and sender_account_balance: {:?}", transaction.raw.value, sender_account_balance);
-            return false
+    /// `validate` against the account of the sender after its pending transactions, so that a
+    /// sender can queue transactions with consecutive nonces.
+    pub fn validate_pending(&self, state: &State, transaction: &Transaction) -> Result<(), TransactionError> {
+        if !transaction.verify_signature() {
+            return Err(TransactionError::InvalidSignature);
         }
-        return true
+        self.validate_pending_verified(state, transaction)
+    }
+
+    /// `validate_pending` for a transaction whose signature was already checked.
+    pub fn validate_pending_verified(&self, state: &State, transaction: &Transaction) -> Result<(), TransactionError> {
+        let account = self.pending_account(state, &transaction.signer(), transaction.raw.nonce);
+        validate_account(state, account, transaction)
+    }
+
+    // Remove a transaction from the mempool, returning it if it was there
+    pub fn remove(&mut self, hash: &H256, reason: EvictionReason) -> Option<Transaction> {
+        let transaction = self.hash_to_transaction.remove(hash)?;
+        self.publish(MempoolEvent::TransactionEvicted { hash: *hash, reason });
+        Some(transaction)
     }
 
     // Remove transactions from the mempool
-    pub fn remove_transactions(&mut self, hashes: &[H256]) {
+    pub fn remove_transactions(&mut self, hashes: &[H256], reason: EvictionReason) {
         for hash in hashes {
-            self.hash_to_transaction.remove(hash);
+            self.remove(hash, reason);
         }
     }
 
@@ -115,4 +336,125 @@ impl Mempool {
         self.hash_to_transaction.len()
     }
 
-}
\ No newline at end of file
+    // Check if the mempool is empty
+    pub fn is_empty(&self) -> bool {
+        self.hash_to_transaction.is_empty()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{default_genesis_seed, ico_keypair};
+    use crate::blockchain::Blockchain;
+    use crate::crypto::key_pair;
+    use crate::transaction::RawTransaction;
+    use ring::signature::KeyPair;
+
+    fn transfer(nonce: u32, value: u64) -> Transaction {
+        transfer_on(Blockchain::new().chain_id(), None, nonce, value)
+    }
+
+    fn transfer_on(chain_id: H256, expiry_height: Option<u64>, nonce: u32, value: u64) -> Transaction {
+        let key = ico_keypair(&default_genesis_seed(), 0);
+        let raw = RawTransaction {
+            from_addr: H160::from_pubkey(key.public_key().as_ref()),
+            to_addr: H160::from_pubkey(key_pair::random().public_key().as_ref()),
+            value,
+            nonce,
+            chain_id,
+            expiry_height,
+        };
+        Transaction::from_raw(raw, &key)
+    }
+
+    #[test]
+    fn validate_checks() {
+        let state = Blockchain::new().state();
+        assert_eq!(validate(&state, &transfer(1, 10)), Ok(()));
+        assert_eq!(
+            validate(&state, &transfer(2, 10)),
+            Err(TransactionError::BadNonce { expected: 1, got: 2 })
+        );
+        assert_eq!(
+            validate(&state, &transfer(1, 10_001)),
+            Err(TransactionError::InsufficientBalance { balance: 10_000, value: 10_001 })
+        );
+        let mut forged = transfer(1, 10);
+        forged.raw.value = 20;
+        assert_eq!(validate(&state, &forged), Err(TransactionError::InvalidSignature));
+    }
+
+    #[test]
+    fn replay_protection() {
+        use crate::block::test::generate_random_block;
+        use crate::crypto::hd::seed_from_phrase;
+
+        let mut blockchain = Blockchain::new();
+        let chain_id = blockchain.chain_id();
+        let other = Blockchain::with_genesis_seed(&seed_from_phrase("another network", "")).chain_id();
+        assert_ne!(chain_id, other);
+        assert_eq!(
+            validate(&blockchain.state(), &transfer_on(other, None, 1, 10)),
+            Err(TransactionError::WrongChain { expected: chain_id, got: other })
+        );
+
+        assert_eq!(validate(&blockchain.state(), &transfer_on(chain_id, Some(1), 1, 10)), Ok(()));
+        blockchain.insert(&generate_random_block(&blockchain.tip()));
+        assert_eq!(
+            validate(&blockchain.state(), &transfer_on(chain_id, Some(1), 1, 10)),
+            Err(TransactionError::Expired { expiry_height: 1, height: 2 })
+        );
+        assert_eq!(validate(&blockchain.state(), &transfer_on(chain_id, Some(2), 1, 10)), Ok(()));
+    }
+
+    #[test]
+    fn unknown_sender_is_rejected_without_panicking() {
+        let state = Blockchain::new().state();
+        let key = key_pair::random();
+        let raw = RawTransaction {
+            from_addr: H160::from_pubkey(key.public_key().as_ref()),
+            to_addr: Default::default(),
+            value: 1,
+            nonce: 1,
+            chain_id: state.chain_id(),
+            expiry_height: None,
+        };
+        let tx = Transaction::from_raw(raw, &key);
+        assert_eq!(
+            validate(&state, &tx),
+            Err(TransactionError::InsufficientBalance { balance: 0, value: 1 })
+        );
+    }
+
+    #[test]
+    fn queued_transactions() {
+        let state = Blockchain::new().state();
+        let mut mempool = Mempool::new();
+        let first = transfer(1, 4_000);
+        let sender = first.raw.from_addr;
+        mempool.insert(first.clone());
+        assert_eq!(mempool.pending_account(&state, &sender, u32::MAX), (1, 6_000));
+        assert_eq!(mempool.pending_account(&state, &sender, 1), (0, 10_000));
+        assert_eq!(mempool.validate_pending(&state, &transfer(2, 6_000)), Ok(()));
+        assert_eq!(
+            mempool.validate_pending(&state, &transfer(2, 6_001)),
+            Err(TransactionError::InsufficientBalance { balance: 6_000, value: 6_001 })
+        );
+        assert_eq!(
+            mempool.validate_pending(&state, &transfer(3, 10)),
+            Err(TransactionError::BadNonce { expected: 2, got: 3 })
+        );
+
+        // the queued transaction is kept until its predecessor is mined, stale ones are not
+        let second = transfer(2, 10);
+        mempool.insert(second.clone());
+        assert!(mempool.get_invalid_transactions(&state).is_empty());
+        assert_eq!(mempool.get_valid_transactions(&state).len(), 1);
+        let mut blockchain = Blockchain::new();
+        blockchain.insert(&crate::block::test::generate_block(&blockchain.tip(), vec![first.clone()]));
+        let invalid = mempool.get_invalid_transactions(&blockchain.state());
+        assert_eq!(invalid.iter().map(|tx| tx.hash()).collect::<Vec<_>>(), vec![first.hash()]);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::network::message::Message;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Process-wide counters and gauges, updated by the subsystems that own the measured values and
+// exported in the Prometheus text format. They are plain atomics so that hot paths such as
+// message handling do not take locks.
+
+pub struct Metrics {
+    /// Blocks found by our miner
+    pub blocks_mined: AtomicU64,
+    /// When the miner was first started, in milliseconds since the epoch, or 0
+    pub mining_started_ms: AtomicU64,
+    /// Blocks inserted into the blockchain, including the genesis block
+    pub chain_blocks: AtomicU64,
+    pub chain_height: AtomicU64,
+    /// Serialized size of all blocks in the blockchain
+    pub chain_bytes: AtomicU64,
+    /// Blocks received from peers, and the sum of their delays since creation
+    pub blocks_received: AtomicU64,
+    pub propagation_delay_ms: AtomicU64,
+    pub orphan_blocks: AtomicU64,
+    pub mempool_transactions: AtomicU64,
+    pub peers: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub messages_received: [AtomicU64; Message::KINDS],
+    pub messages_sent: [AtomicU64; Message::KINDS],
+}
+
+static METRICS: Metrics = Metrics::new();
+
+/// The metrics of this process
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Metrics {
+            blocks_mined: AtomicU64::new(0),
+            mining_started_ms: AtomicU64::new(0),
+            chain_blocks: AtomicU64::new(0),
+            chain_height: AtomicU64::new(0),
+            chain_bytes: AtomicU64::new(0),
+            blocks_received: AtomicU64::new(0),
+            propagation_delay_ms: AtomicU64::new(0),
+            orphan_blocks: AtomicU64::new(0),
+            mempool_transactions: AtomicU64::new(0),
+            peers: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            messages_received: [const { AtomicU64::new(0) }; Message::KINDS],
+            messages_sent: [const { AtomicU64::new(0) }; Message::KINDS],
+        }
+    }
+
+    /// Record that the miner started, unless it was started before
+    pub fn mining_started(&self) {
+        let _ = self
+            .mining_started_ms
+            .compare_exchange(0, now_ms(), Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Record a message of `bytes` bytes (without the length prefix) read from a peer
+    pub fn message_received(&self, msg: &Message, bytes: usize) {
+        self.messages_received[msg.kind()].fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add((bytes + std::mem::size_of::<u32>()) as u64, Ordering::Relaxed);
+    }
+
+    /// Record a message of `bytes` bytes (without the length prefix) queued for a peer
+    pub fn message_sent(&self, msg: &Message, bytes: usize) {
+        self.messages_sent[msg.kind()].fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add((bytes + std::mem::size_of::<u32>()) as u64, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let get = |metric: &AtomicU64| metric.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let mut single = |name: &str, kind: &str, help: &str, value: f64| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+
+        let blocks_mined = get(&self.blocks_mined);
+        single("bitcoin_blocks_mined_total", "counter", "Blocks found by the local miner.", blocks_mined as f64);
+        let started = get(&self.mining_started_ms);
+        let rate = if started == 0 {
+            0.0
+        } else {
+            blocks_mined as f64 * 1000.0 / (now_ms().saturating_sub(started).max(1)) as f64
+        };
+        single("bitcoin_miner_block_rate", "gauge", "Blocks mined per second since the miner was started.", rate);
+        let chain_blocks = get(&self.chain_blocks);
+        let chain_bytes = get(&self.chain_bytes);
+        single("bitcoin_chain_blocks", "gauge", "Blocks in the blockchain, on all forks.", chain_blocks as f64);
+        single("bitcoin_chain_height", "gauge", "Height of the longest chain.", get(&self.chain_height) as f64);
+        single("bitcoin_chain_bytes", "gauge", "Serialized size of all blocks in the blockchain.", chain_bytes as f64);
+        let average_size = if chain_blocks == 0 { 0.0 } else { chain_bytes as f64 / chain_blocks as f64 };
+        single("bitcoin_block_size_bytes_avg", "gauge", "Average serialized size of the blocks in the blockchain.", average_size);
+        let received = get(&self.blocks_received);
+        let delay = get(&self.propagation_delay_ms);
+        single("bitcoin_blocks_received_total", "counter", "Blocks received from peers and inserted into the blockchain.", received as f64);
+        single("bitcoin_block_propagation_delay_ms_sum", "counter", "Sum of the delays between creation and reception of received blocks.", delay as f64);
+        let average_delay = if received == 0 { 0.0 } else { delay as f64 / received as f64 };
+        single("bitcoin_block_propagation_delay_ms_avg", "gauge", "Average delay between creation and reception of received blocks.", average_delay);
+        single("bitcoin_orphan_blocks", "gauge", "Blocks waiting in the orphan buffer for their parent.", get(&self.orphan_blocks) as f64);
+        single("bitcoin_mempool_transactions", "gauge", "Transactions in the mempool.", get(&self.mempool_transactions) as f64);
+        single("bitcoin_peers", "gauge", "Connected peers.", get(&self.peers) as f64);
+        single("bitcoin_network_received_bytes_total", "counter", "Bytes of messages received from peers.", get(&self.bytes_received) as f64);
+        single("bitcoin_network_sent_bytes_total", "counter", "Bytes of messages sent to peers.", get(&self.bytes_sent) as f64);
+
+        for (name, help, counters) in [
+            ("bitcoin_network_received_messages_total", "Messages received from peers, by type.", &self.messages_received),
+            ("bitcoin_network_sent_messages_total", "Messages sent to peers, by type.", &self.messages_sent),
+        ] {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            for (kind, counter) in Message::KIND_NAMES.iter().zip(counters.iter()) {
+                writeln!(out, "{}{{type=\"{}\"}} {}", name, kind, get(counter)).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_exposition_format() {
+        let metrics = Metrics::new();
+        metrics.blocks_mined.store(3, Ordering::Relaxed);
+        metrics.chain_blocks.store(4, Ordering::Relaxed);
+        metrics.chain_bytes.store(400, Ordering::Relaxed);
+        metrics.message_sent(&Message::Ping(0), 12);
+        let text = metrics.render();
+        assert!(text.contains("# TYPE bitcoin_blocks_mined_total counter\nbitcoin_blocks_mined_total 3\n"));
+        assert!(text.contains("\nbitcoin_block_size_bytes_avg 100\n"));
+        assert!(text.contains("\nbitcoin_block_propagation_delay_ms_avg 0\n"));
+        assert!(text.contains("\nbitcoin_network_sent_bytes_total 16\n"));
+        assert!(text.contains("\nbitcoin_network_sent_messages_total{type=\"ping\"} 1\n"));
+        assert!(text.contains("\nbitcoin_network_sent_messages_total{type=\"transactions\"} 0\n"));
+    }
+}
@@ -1,16 +1,17 @@
 use crate::crypto::merkle::MerkleTree;
-use crate::mempool::Mempool;
-use crate::network::server::Handle as ServerHandle;
+use crate::mempool::{EvictionReason, Mempool};
+use crate::metrics::metrics;
+use crate::network::inventory::Handle as InventoryHandle;
 use crate::blockchain::{BlockOrigin, Blockchain};
 use std::sync::{Arc, Mutex};
 use crate::transaction::SignedTransaction as Transaction;
 use crate::block::{Block, Header, Content};
 use crate::crypto::hash::{Hashable, H256};
-use crate::network::message::Message::NewBlockHashes;
 
 use log::{debug, info};
 
 use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use std::sync::atomic::Ordering;
 use std::time::{self, SystemTime};
 
 use std::thread;
@@ -30,7 +31,7 @@ pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
     operating_state: OperatingState,
-    server: ServerHandle,
+    inventory: InventoryHandle,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
     start_time: Option<SystemTime>,
@@ -45,14 +46,14 @@ pub struct Handle {
 }
 
 pub fn new(
-    server: &ServerHandle, blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>,
+    inventory: &InventoryHandle, blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
 
     let ctx = Context {
         control_chan: signal_chan_receiver,
         operating_state: OperatingState::Paused,
-        server: server.clone(),
+        inventory: inventory.clone(),
         blockchain: blockchain.clone(),
         mempool: mempool.clone(),
         start_time: None,
@@ -121,7 +122,7 @@ impl Context {
                 // Average delay time of the received blocks
                 let mut total_delay = 0;
                 let mut total_received = 0;
-                for (hash, origin) in blockchain.hash_to_origin.iter() {
+                for (_hash, origin) in blockchain.hash_to_origin.iter() {
                     if let BlockOrigin::Received{delay_ms} = origin {
                         total_delay += delay_ms;
                         total_received += 1;
@@ -136,9 +137,10 @@ impl Context {
                 info!("Miner starting in continuous mode with lambda {}", i);
                 self.operating_state = OperatingState::Run(i);
                 // set the miner start time:
-                if self.start_time == None {
+                if self.start_time.is_none() {
                     self.start_time = Some(SystemTime::now());
                 }
+                metrics().mining_started();
             }
         }
     }
@@ -175,7 +177,7 @@ impl Context {
             // TODO: actual mining
             if let OperatingState::Run(i) = self.operating_state {
                 if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
+                    let interval = time::Duration::from_micros(i);
                     thread::sleep(interval);
                 }
                 let mut blockchain = self.blockchain.lock().unwrap();
@@ -242,7 +244,7 @@ impl Context {
 
                     // remove transactions from mempool
                     let hashes: Vec<H256> = transactions.iter().map(|tx| tx.hash()).collect();
-                    self.mempool.lock().unwrap().remove_transactions(&hashes);
+                    self.mempool.lock().unwrap().remove_transactions(&hashes, EvictionReason::Included);
 
                     // remove invalid transactions from mempool
                     let state = &blockchain.state();
@@ -253,17 +255,18 @@ impl Context {
                     //     // the latest state
                     //     debug!("The latest state of account: {:?}", &blockchain.state().get(&transaction.raw.from_addr));
                     // }
-                    self.mempool.lock().unwrap().remove_transactions(&invalid_hashes);
+                    self.mempool.lock().unwrap().remove_transactions(&invalid_hashes, EvictionReason::Invalid);
 
                     info!("Block mined: parent - {:?}, hash - {:?}, nonce - {:?}, merkle_root - {:?}, # txs - {:?}", new_block.header.parent, new_block.hash(), new_block.header.nonce, new_block.header.merkle_root, new_block.content.transactions.len());
                     self.total_blocks_mined += 1;
+                    metrics().blocks_mined.fetch_add(1, Ordering::Relaxed);
                     info!("Blockchain height: {}", blockchain.length_of_longest_chain());
                     info!("# Hashs: {}", blockchain.hash_to_block.len());
                     info!("The latest state: {:?}", &blockchain.state());
                     debug!("Removing {:?} of invalid transactions from mempool based on updated state", invalid_hashes.len());
                     // sleep for 5 secs
                     // std::thread::sleep(std::time::Duration::from_secs(5));
-                    self.server.broadcast(NewBlockHashes(vec![new_block.hash()]));
+                    self.inventory.announce_blocks(vec![new_block.hash()]);
                 }
             }
         }
@@ -0,0 +1,196 @@
+use super::peer::Direction;
+use super::server::{Handle as ServerHandle, PeerEvent};
+use crossbeam::channel::{self, Receiver, Sender};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Delay before the first reconnection attempt; doubled after every failure.
+const INITIAL_BACKOFF_MS: u64 = 1000;
+/// Upper bound of the reconnection delay.
+const MAX_BACKOFF_MS: u64 = 60_000;
+
+enum ControlSignal {
+    AddPeer(SocketAddr),
+    RemovePeer(SocketAddr),
+    ListPeers(Sender<Vec<SocketAddr>>),
+}
+
+/// Connection state of a static peer.
+struct PeerState {
+    /// Number of failed attempts since the last successful connection.
+    failures: u32,
+    /// When to dial next, or `None` while the peer is connected.
+    next_attempt: Option<Instant>,
+}
+
+/// Keeps the server connected to a set of static peers, redialing them with exponential backoff
+/// whenever the connection fails or drops.
+pub struct Context {
+    control_chan: Receiver<ControlSignal>,
+    events: Receiver<PeerEvent>,
+    server: ServerHandle,
+    peers: HashMap<SocketAddr, PeerState>,
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    control_chan: Sender<ControlSignal>,
+}
+
+pub fn new(server: &ServerHandle) -> (Context, Handle) {
+    let (control_sender, control_receiver) = channel::unbounded();
+    let ctx = Context {
+        control_chan: control_receiver,
+        events: server.subscribe(),
+        server: server.clone(),
+        peers: HashMap::new(),
+    };
+    let handle = Handle {
+        control_chan: control_sender,
+    };
+    (ctx, handle)
+}
+
+impl Handle {
+    /// Add a static peer. It is dialed right away and redialed after every disconnect.
+    pub fn add_peer(&self, addr: SocketAddr) {
+        self.control_chan.send(ControlSignal::AddPeer(addr)).unwrap();
+    }
+
+    /// Stop reconnecting to a static peer. An existing connection is left untouched.
+    pub fn remove_peer(&self, addr: SocketAddr) {
+        self.control_chan
+            .send(ControlSignal::RemovePeer(addr))
+            .unwrap();
+    }
+
+    /// List the static peers.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        let (sender, receiver) = channel::unbounded();
+        self.control_chan
+            .send(ControlSignal::ListPeers(sender))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+}
+
+impl Context {
+    pub fn start(mut self) {
+        thread::Builder::new()
+            .name("connector".to_string())
+            .spawn(move || {
+                self.connector_loop();
+                warn!("Peer connector exited");
+            })
+            .unwrap();
+    }
+
+    fn connector_loop(&mut self) {
+        loop {
+            self.dial_due_peers();
+            let timeout = match self.peers.values().filter_map(|p| p.next_attempt).min() {
+                Some(t) => t.saturating_duration_since(Instant::now()),
+                None => Duration::from_secs(3600),
+            };
+            channel::select! {
+                recv(self.control_chan) -> signal => match signal {
+                    Ok(signal) => self.handle_control_signal(signal),
+                    Err(_) => return,
+                },
+                recv(self.events) -> event => match event {
+                    Ok(event) => self.handle_peer_event(event),
+                    Err(_) => return,
+                },
+                default(timeout) => {}
+            }
+        }
+    }
+
+    fn handle_control_signal(&mut self, signal: ControlSignal) {
+        match signal {
+            ControlSignal::AddPeer(addr) => {
+                debug!("Adding static peer {}", addr);
+                self.peers.entry(addr).or_insert(PeerState {
+                    failures: 0,
+                    next_attempt: Some(Instant::now()),
+                });
+            }
+            ControlSignal::RemovePeer(addr) => {
+                debug!("Removing static peer {}", addr);
+                self.peers.remove(&addr);
+            }
+            ControlSignal::ListPeers(result_chan) => {
+                result_chan.send(self.peers.keys().cloned().collect()).unwrap();
+            }
+        }
+    }
+
+    fn handle_peer_event(&mut self, event: PeerEvent) {
+        // only outgoing connections are dialed to the configured address
+        if let PeerEvent::Disconnected(info) = event {
+            if info.direction != Direction::Outgoing {
+                return;
+            }
+            if let Some(state) = self.peers.get_mut(&info.addr) {
+                info!("Static peer {} disconnected, reconnecting", info.addr);
+                state.failures = 0;
+                state.next_attempt = Some(Instant::now() + backoff(0));
+            }
+        }
+    }
+
+    fn dial_due_peers(&mut self) {
+        let now = Instant::now();
+        let due: Vec<SocketAddr> = self
+            .peers
+            .iter()
+            .filter(|(_, state)| state.next_attempt.is_some_and(|t| t <= now))
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in due {
+            let result = self.server.connect(addr);
+            let state = self.peers.get_mut(&addr).unwrap();
+            match result {
+                Ok(_) => {
+                    info!("Connected to outgoing peer {}", addr);
+                    state.failures = 0;
+                    state.next_attempt = None;
+                }
+                Err(e) => {
+                    let delay = backoff(state.failures);
+                    error!(
+                        "Error connecting to peer {}, retrying in {} ms: {}",
+                        addr,
+                        delay.as_millis(),
+                        e
+                    );
+                    state.failures = state.failures.saturating_add(1);
+                    state.next_attempt = Some(Instant::now() + delay);
+                }
+            }
+        }
+    }
+}
+
+/// Reconnection delay after `failures` consecutive failed attempts.
+fn backoff(failures: u32) -> Duration {
+    let factor = 1u64.checked_shl(failures.min(16)).unwrap_or(u64::MAX);
+    Duration::from_millis(INITIAL_BACKOFF_MS.saturating_mul(factor).min(MAX_BACKOFF_MS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_and_saturates() {
+        assert_eq!(backoff(0), Duration::from_millis(1000));
+        assert_eq!(backoff(1), Duration::from_millis(2000));
+        assert_eq!(backoff(3), Duration::from_millis(8000));
+        assert_eq!(backoff(10), Duration::from_millis(MAX_BACKOFF_MS));
+        assert_eq!(backoff(u32::MAX), Duration::from_millis(MAX_BACKOFF_MS));
+    }
+}
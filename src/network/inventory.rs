@@ -0,0 +1,250 @@
+use super::message::Message;
+use super::server::{Handle as ServerHandle, PeerEvent};
+use crate::crypto::hash::H256;
+use crossbeam::channel::{self, Receiver, Sender};
+use log::{trace, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often queued transaction announcements are flushed to each peer.
+const TRICKLE_INTERVAL_MS: u64 = 200;
+/// Maximum number of hashes in one announcement.
+const MAX_ANNOUNCEMENT: usize = 1000;
+/// Number of hashes remembered per peer before the oldest ones are forgotten.
+const KNOWN_INVENTORY_CAPACITY: usize = 50_000;
+
+enum ControlSignal {
+    MarkKnown(SocketAddr, Vec<H256>),
+    AnnounceBlocks(Vec<H256>),
+    AnnounceTransactions(Vec<H256>),
+}
+
+/// A bounded set of hashes that a peer is known to have, forgetting the oldest entries first.
+pub struct KnownInventory {
+    set: HashSet<H256>,
+    order: VecDeque<H256>,
+    capacity: usize,
+}
+
+impl KnownInventory {
+    pub fn new(capacity: usize) -> Self {
+        KnownInventory {
+            set: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Record a hash. Returns `false` if it was already known.
+    pub fn insert(&mut self, hash: H256) -> bool {
+        if !self.set.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.set.remove(&oldest);
+        }
+        true
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.set.contains(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+struct PeerState {
+    known: KnownInventory,
+    /// Transaction hashes waiting for the next trickle
+    queued_transactions: Vec<H256>,
+}
+
+/// Announces new blocks and transactions to the peers that do not know about them yet. Blocks are
+/// announced right away, while transactions are batched and trickled out periodically.
+pub struct Context {
+    control_chan: Receiver<ControlSignal>,
+    events: Receiver<PeerEvent>,
+    server: ServerHandle,
+    peers: HashMap<SocketAddr, PeerState>,
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    control_chan: Sender<ControlSignal>,
+}
+
+pub fn new(server: &ServerHandle) -> (Context, Handle) {
+    let (control_sender, control_receiver) = channel::unbounded();
+    let ctx = Context {
+        control_chan: control_receiver,
+        events: server.subscribe(),
+        server: server.clone(),
+        peers: HashMap::new(),
+    };
+    let handle = Handle {
+        control_chan: control_sender,
+    };
+    (ctx, handle)
+}
+
+impl Handle {
+    /// Record that a peer has the given blocks or transactions, e.g. because it announced or sent
+    /// them to us. They will not be announced back to it.
+    pub fn mark_known(&self, addr: SocketAddr, hashes: Vec<H256>) {
+        self.control_chan
+            .send(ControlSignal::MarkKnown(addr, hashes))
+            .unwrap();
+    }
+
+    /// Announce blocks to all peers that do not know them yet.
+    pub fn announce_blocks(&self, hashes: Vec<H256>) {
+        self.control_chan
+            .send(ControlSignal::AnnounceBlocks(hashes))
+            .unwrap();
+    }
+
+    /// Queue transactions to be announced to all peers that do not know them yet.
+    pub fn announce_transactions(&self, hashes: Vec<H256>) {
+        self.control_chan
+            .send(ControlSignal::AnnounceTransactions(hashes))
+            .unwrap();
+    }
+}
+
+impl PeerState {
+    fn new() -> Self {
+        PeerState {
+            known: KnownInventory::new(KNOWN_INVENTORY_CAPACITY),
+            queued_transactions: Vec::new(),
+        }
+    }
+
+    /// Keep the hashes this peer does not know yet, and remember them as known from now on.
+    fn filter_unknown(&mut self, hashes: &[H256]) -> Vec<H256> {
+        hashes
+            .iter()
+            .filter(|hash| self.known.insert(**hash))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Context {
+    pub fn start(mut self) {
+        thread::Builder::new()
+            .name("inventory".to_string())
+            .spawn(move || {
+                self.inventory_loop();
+                warn!("Inventory relay exited");
+            })
+            .unwrap();
+    }
+
+    fn inventory_loop(&mut self) {
+        let interval = Duration::from_millis(TRICKLE_INTERVAL_MS);
+        let mut next_trickle = Instant::now() + interval;
+        loop {
+            let timeout = next_trickle.saturating_duration_since(Instant::now());
+            channel::select! {
+                recv(self.control_chan) -> signal => match signal {
+                    Ok(signal) => self.handle_control_signal(signal),
+                    Err(_) => return,
+                },
+                recv(self.events) -> event => match event {
+                    Ok(PeerEvent::Connected(info)) => {
+                        self.peers.insert(info.addr, PeerState::new());
+                    }
+                    Ok(PeerEvent::Disconnected(info)) => {
+                        self.peers.remove(&info.addr);
+                    }
+                    Err(_) => return,
+                },
+                default(timeout) => {
+                    self.trickle();
+                    next_trickle = Instant::now() + interval;
+                }
+            }
+        }
+    }
+
+    fn handle_control_signal(&mut self, signal: ControlSignal) {
+        match signal {
+            ControlSignal::MarkKnown(addr, hashes) => {
+                if let Some(peer) = self.peers.get_mut(&addr) {
+                    for hash in hashes {
+                        peer.known.insert(hash);
+                    }
+                }
+            }
+            ControlSignal::AnnounceBlocks(hashes) => {
+                for (addr, peer) in self.peers.iter_mut() {
+                    let unknown = peer.filter_unknown(&hashes);
+                    if !unknown.is_empty() {
+                        trace!("Announcing {} blocks to {}", unknown.len(), addr);
+                        self.server.send(*addr, Message::NewBlockHashes(unknown));
+                    }
+                }
+            }
+            ControlSignal::AnnounceTransactions(hashes) => {
+                for peer in self.peers.values_mut() {
+                    let unknown = peer.filter_unknown(&hashes);
+                    peer.queued_transactions.extend(unknown);
+                }
+            }
+        }
+    }
+
+    /// Flush the queued transaction announcements of every peer.
+    fn trickle(&mut self) {
+        for (addr, peer) in self.peers.iter_mut() {
+            if peer.queued_transactions.is_empty() {
+                continue;
+            }
+            let queued = std::mem::take(&mut peer.queued_transactions);
+            trace!("Announcing {} transactions to {}", queued.len(), addr);
+            for batch in queued.chunks(MAX_ANNOUNCEMENT) {
+                self.server
+                    .send(*addr, Message::NewTransactionHashes(batch.to_vec()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::tests::generate_random_hash;
+
+    #[test]
+    fn known_inventory_evicts_oldest() {
+        let mut known = KnownInventory::new(2);
+        let hashes: Vec<H256> = (0..3).map(|_| generate_random_hash()).collect();
+        assert!(known.insert(hashes[0]));
+        assert!(!known.insert(hashes[0]));
+        assert!(known.insert(hashes[1]));
+        assert!(known.insert(hashes[2]));
+        assert_eq!(known.len(), 2);
+        assert!(!known.contains(&hashes[0]));
+        assert!(known.contains(&hashes[1]));
+        assert!(known.contains(&hashes[2]));
+    }
+
+    #[test]
+    fn filter_unknown_skips_known_hashes() {
+        let mut peer = PeerState::new();
+        let hashes: Vec<H256> = (0..3).map(|_| generate_random_hash()).collect();
+        peer.known.insert(hashes[1]);
+        assert_eq!(peer.filter_unknown(&hashes), vec![hashes[0], hashes[2]]);
+        assert!(peer.filter_unknown(&hashes).is_empty());
+    }
+}
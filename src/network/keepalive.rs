@@ -0,0 +1,267 @@
+use super::message::Message;
+use super::server::{Handle as ServerHandle, PeerEvent};
+use crossbeam::channel::{self, Receiver, Sender};
+use log::{debug, trace, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often each peer is pinged.
+const PING_INTERVAL_MS: u64 = 5000;
+/// Number of consecutive unanswered pings after which a peer is disconnected.
+const MAX_MISSED_PONGS: u32 = 3;
+
+enum ControlSignal {
+    Pong(SocketAddr, u64),
+    PingNow,
+    Stats(Sender<Vec<PeerLatency>>),
+}
+
+/// Round-trip time statistics of a peer, in milliseconds.
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerLatency {
+    pub addr: SocketAddr,
+    pub last_rtt_ms: Option<f64>,
+    pub min_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+    pub avg_rtt_ms: Option<f64>,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    /// Consecutive pings that were not answered in time.
+    pub missed: u32,
+}
+
+struct PeerState {
+    /// Nonce and send time of the ping we are waiting for.
+    outstanding: Option<(u64, Instant)>,
+    missed: u32,
+    pings_sent: u64,
+    pongs_received: u64,
+    last_rtt: Option<Duration>,
+    min_rtt: Option<Duration>,
+    max_rtt: Option<Duration>,
+    total_rtt: Duration,
+}
+
+impl PeerState {
+    fn new() -> Self {
+        PeerState {
+            outstanding: None,
+            missed: 0,
+            pings_sent: 0,
+            pongs_received: 0,
+            last_rtt: None,
+            min_rtt: None,
+            max_rtt: None,
+            total_rtt: Duration::default(),
+        }
+    }
+
+    fn record_rtt(&mut self, rtt: Duration) {
+        self.outstanding = None;
+        self.missed = 0;
+        self.pongs_received += 1;
+        self.last_rtt = Some(rtt);
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |m| m.min(rtt)));
+        self.max_rtt = Some(self.max_rtt.map_or(rtt, |m| m.max(rtt)));
+        self.total_rtt += rtt;
+    }
+
+    fn latency(&self, addr: SocketAddr) -> PeerLatency {
+        let to_ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        PeerLatency {
+            addr,
+            last_rtt_ms: self.last_rtt.map(to_ms),
+            min_rtt_ms: self.min_rtt.map(to_ms),
+            max_rtt_ms: self.max_rtt.map(to_ms),
+            avg_rtt_ms: if self.pongs_received > 0 {
+                Some(to_ms(self.total_rtt) / self.pongs_received as f64)
+            } else {
+                None
+            },
+            pings_sent: self.pings_sent,
+            pongs_received: self.pongs_received,
+            missed: self.missed,
+        }
+    }
+}
+
+/// Periodically pings every connected peer, measures round-trip times and disconnects peers
+/// that stop answering.
+pub struct Context {
+    control_chan: Receiver<ControlSignal>,
+    events: Receiver<PeerEvent>,
+    server: ServerHandle,
+    peers: HashMap<SocketAddr, PeerState>,
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    control_chan: Sender<ControlSignal>,
+}
+
+pub fn new(server: &ServerHandle) -> (Context, Handle) {
+    let (control_sender, control_receiver) = channel::unbounded();
+    let ctx = Context {
+        control_chan: control_receiver,
+        events: server.subscribe(),
+        server: server.clone(),
+        peers: HashMap::new(),
+    };
+    let handle = Handle {
+        control_chan: control_sender,
+    };
+    (ctx, handle)
+}
+
+impl Handle {
+    /// Report a `Pong` received from a peer.
+    pub fn pong(&self, addr: SocketAddr, nonce: u64) {
+        self.control_chan
+            .send(ControlSignal::Pong(addr, nonce))
+            .unwrap();
+    }
+
+    /// Ping all peers that have no ping in flight, without waiting for the next interval.
+    pub fn ping_now(&self) {
+        self.control_chan.send(ControlSignal::PingNow).unwrap();
+    }
+
+    /// Get the round-trip time statistics of all connected peers.
+    pub fn stats(&self) -> Vec<PeerLatency> {
+        let (sender, receiver) = channel::unbounded();
+        self.control_chan
+            .send(ControlSignal::Stats(sender))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+}
+
+impl Context {
+    pub fn start(mut self) {
+        thread::Builder::new()
+            .name("keepalive".to_string())
+            .spawn(move || {
+                self.keepalive_loop();
+                warn!("Keepalive exited");
+            })
+            .unwrap();
+    }
+
+    fn keepalive_loop(&mut self) {
+        let interval = Duration::from_millis(PING_INTERVAL_MS);
+        let mut next_round = Instant::now() + interval;
+        loop {
+            let timeout = next_round.saturating_duration_since(Instant::now());
+            channel::select! {
+                recv(self.control_chan) -> signal => match signal {
+                    Ok(signal) => self.handle_control_signal(signal),
+                    Err(_) => return,
+                },
+                recv(self.events) -> event => match event {
+                    Ok(PeerEvent::Connected(info)) => {
+                        self.peers.insert(info.addr, PeerState::new());
+                    }
+                    Ok(PeerEvent::Disconnected(info)) => {
+                        self.peers.remove(&info.addr);
+                    }
+                    Err(_) => return,
+                },
+                default(timeout) => {
+                    self.ping_round();
+                    next_round = Instant::now() + interval;
+                }
+            }
+        }
+    }
+
+    fn handle_control_signal(&mut self, signal: ControlSignal) {
+        match signal {
+            ControlSignal::Pong(addr, nonce) => {
+                let state = match self.peers.get_mut(&addr) {
+                    Some(state) => state,
+                    None => return,
+                };
+                match state.outstanding {
+                    Some((expected, sent_at)) if expected == nonce => {
+                        let rtt = sent_at.elapsed();
+                        trace!("Pong from {} after {:?}", addr, rtt);
+                        state.record_rtt(rtt);
+                    }
+                    _ => debug!("Ignoring unexpected pong {} from {}", nonce, addr),
+                }
+            }
+            ControlSignal::PingNow => {
+                let idle: Vec<SocketAddr> = self
+                    .peers
+                    .iter()
+                    .filter(|(_, state)| state.outstanding.is_none())
+                    .map(|(addr, _)| *addr)
+                    .collect();
+                for addr in idle {
+                    self.ping(addr);
+                }
+            }
+            ControlSignal::Stats(result_chan) => {
+                let stats = self
+                    .peers
+                    .iter()
+                    .map(|(addr, state)| state.latency(*addr))
+                    .collect();
+                result_chan.send(stats).unwrap();
+            }
+        }
+    }
+
+    /// Count unanswered pings, drop unresponsive peers and ping the rest.
+    fn ping_round(&mut self) {
+        let addrs: Vec<SocketAddr> = self.peers.keys().cloned().collect();
+        for addr in addrs {
+            let state = self.peers.get_mut(&addr).unwrap();
+            if state.outstanding.is_some() {
+                state.missed += 1;
+                if state.missed >= MAX_MISSED_PONGS {
+                    warn!("Peer {} missed {} pongs, disconnecting", addr, state.missed);
+                    self.peers.remove(&addr);
+                    self.server.disconnect(addr);
+                    continue;
+                }
+            }
+            self.ping(addr);
+        }
+    }
+
+    fn ping(&mut self, addr: SocketAddr) {
+        let nonce: u64 = rand::random();
+        let state = self.peers.get_mut(&addr).unwrap();
+        state.outstanding = Some((nonce, Instant::now()));
+        state.pings_sent += 1;
+        self.server.send(addr, Message::Ping(nonce));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_statistics() {
+        let addr: SocketAddr = "127.0.0.1:6000".parse().unwrap();
+        let mut state = PeerState::new();
+        assert!(state.latency(addr).avg_rtt_ms.is_none());
+        state.outstanding = Some((1, Instant::now()));
+        state.missed = 2;
+        state.record_rtt(Duration::from_millis(10));
+        state.record_rtt(Duration::from_millis(30));
+        let latency = state.latency(addr);
+        assert!(state.outstanding.is_none());
+        assert_eq!(latency.missed, 0);
+        assert_eq!(latency.pongs_received, 2);
+        assert_eq!(latency.last_rtt_ms, Some(30.0));
+        assert_eq!(latency.min_rtt_ms, Some(10.0));
+        assert_eq!(latency.max_rtt_ms, Some(30.0));
+        assert_eq!(latency.avg_rtt_ms, Some(20.0));
+    }
+}
@@ -1,16 +1,67 @@
 use serde::{Serialize, Deserialize};
-use crate::block::Block;
+use crate::block::{Block, CompactBlock};
 use crate::crypto::hash::H256;
+use crate::spv::InclusionProof;
 use crate::transaction::SignedTransaction as Transaction;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    Ping(String),
-    Pong(String),
+    Ping(u64),
+    Pong(u64),
     NewBlockHashes(Vec<H256>),
     GetBlocks(Vec<H256>),
     Blocks(Vec<Block>),
+    GetCompactBlocks(Vec<H256>),
+    CompactBlocks(Vec<(H256, CompactBlock)>),
+    GetBlockTransactions(H256, Vec<u32>),
+    BlockTransactions(H256, Vec<Transaction>),
     NewTransactionHashes(Vec<H256>),
     GetTransactions(Vec<H256>),
     Transactions(Vec<Transaction>),
-}
\ No newline at end of file
+    /// Ask for the inclusion proofs of transactions, by hash
+    GetMerkleProofs(Vec<H256>),
+    /// Inclusion proofs of transactions confirmed on the sender's longest chain
+    MerkleProofs(Vec<InclusionProof>),
+}
+
+impl Message {
+    /// Number of message types
+    pub const KINDS: usize = 14;
+    /// Names of the message types, indexed by `kind()`
+    pub const KIND_NAMES: [&'static str; Message::KINDS] = [
+        "ping",
+        "pong",
+        "new_block_hashes",
+        "get_blocks",
+        "blocks",
+        "get_compact_blocks",
+        "compact_blocks",
+        "get_block_transactions",
+        "block_transactions",
+        "new_transaction_hashes",
+        "get_transactions",
+        "transactions",
+        "get_merkle_proofs",
+        "merkle_proofs",
+    ];
+
+    /// Index of the message type
+    pub fn kind(&self) -> usize {
+        match self {
+            Message::Ping(_) => 0,
+            Message::Pong(_) => 1,
+            Message::NewBlockHashes(_) => 2,
+            Message::GetBlocks(_) => 3,
+            Message::Blocks(_) => 4,
+            Message::GetCompactBlocks(_) => 5,
+            Message::CompactBlocks(_) => 6,
+            Message::GetBlockTransactions(_, _) => 7,
+            Message::BlockTransactions(_, _) => 8,
+            Message::NewTransactionHashes(_) => 9,
+            Message::GetTransactions(_) => 10,
+            Message::Transactions(_) => 11,
+            Message::GetMerkleProofs(_) => 12,
+            Message::MerkleProofs(_) => 13,
+        }
+    }
+}
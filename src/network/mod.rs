@@ -1,3 +1,6 @@
+pub mod connector;
+pub mod inventory;
+pub mod keepalive;
 pub mod message;
 pub mod peer;
 pub mod server;
@@ -1,6 +1,8 @@
 use super::message;
+use crate::metrics::metrics;
 use log::{trace, warn};
 use mio;
+use serde::Serialize;
 use mio_extras::channel;
 use std::convert::TryInto;
 use std::io::{Read, Write};
@@ -196,7 +198,7 @@ pub fn new(
     Ok((ctx, handle))
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
 pub enum Direction {
     Incoming,
     Outgoing,
@@ -218,9 +220,15 @@ pub struct Handle {
 }
 
 impl Handle {
+    /// The remote address of this peer.
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
     pub fn write(&self, msg: message::Message) {
         // TODO: return result
         let buffer = bincode::serialize(&msg).unwrap();
+        metrics().message_sent(&msg, buffer.len());
         if self.write_queue.send(buffer).is_err() {
             warn!("Failed to send write request for peer {}, channel detached", self.addr);
         }
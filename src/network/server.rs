@@ -4,6 +4,9 @@ use crossbeam::channel as cbchannel;
 use log::{debug, error, info, trace, warn};
 use mio::{self, net};
 use mio_extras::channel;
+use serde::Serialize;
+use crate::metrics::metrics;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::thread;
 
@@ -25,6 +28,7 @@ pub fn new(
         poll: mio::Poll::new()?,
         control_chan: control_signal_receiver,
         new_msg_chan: msg_sink,
+        event_subscribers: vec![],
         _handle: handle.clone(),
     };
     Ok((ctx, handle))
@@ -37,6 +41,7 @@ pub struct Context {
     poll: mio::Poll,
     control_chan: channel::Receiver<ControlSignal>,
     new_msg_chan: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    event_subscribers: Vec<cbchannel::Sender<PeerEvent>>,
     _handle: Handle,
 }
 
@@ -62,8 +67,7 @@ impl Context {
         let key: usize = vacant.key();
         if key >= MAX_INCOMING_CLIENT {
             // too many connections
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(std::io::Error::other(
                 "max peer reached, cannot accept new connections",
             ));
         }
@@ -90,13 +94,72 @@ impl Context {
         )?;
 
         // insert the context and return the handle
+        let info = PeerInfo {
+            addr: ctx.addr,
+            direction: ctx.direction,
+        };
         vacant.insert(ctx);
         // record the key of this peer
         self.peer_list.push(key);
         trace!("Registering peer with event token={}", key);
+        self.publish(PeerEvent::Connected(info));
         Ok(handle)
     }
 
+    /// Remove a peer from the connection set and notify the subscribers.
+    fn remove_peer(&mut self, peer_id: usize) {
+        let peer = self.peers.remove(peer_id);
+        // the sockets are closed once the context is dropped, but deregister explicitly so that
+        // no more events arrive for a token that may be reused by the next peer
+        let _ = self.poll.deregister(&peer.stream);
+        let _ = self.poll.deregister(&peer.writer.queue);
+        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
+        self.peer_list.swap_remove(index);
+        self.publish(PeerEvent::Disconnected(PeerInfo {
+            addr: peer.addr,
+            direction: peer.direction,
+        }));
+    }
+
+    /// Send a connection lifecycle event to all subscribers, dropping those that hung up.
+    fn publish(&mut self, event: PeerEvent) {
+        metrics().peers.store(self.peer_list.len() as u64, Ordering::Relaxed);
+        self.event_subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+    }
+
+    /// List the peers that are currently connected.
+    fn peer_infos(&self) -> Vec<PeerInfo> {
+        self.peer_list
+            .iter()
+            .map(|peer_id| {
+                let peer = &self.peers[*peer_id];
+                PeerInfo {
+                    addr: peer.addr,
+                    direction: peer.direction,
+                }
+            })
+            .collect()
+    }
+
+    /// Close the connection to a peer. Returns whether such a peer was connected.
+    fn disconnect(&mut self, addr: &std::net::SocketAddr) -> bool {
+        let peer_id = match self
+            .peer_list
+            .iter()
+            .find(|peer_id| self.peers[**peer_id].addr == *addr)
+        {
+            Some(peer_id) => *peer_id,
+            None => return false,
+        };
+        info!("Disconnecting peer {}", addr);
+        if let Err(e) = self.peers[peer_id].stream.shutdown(std::net::Shutdown::Both) {
+            warn!("Error shutting down connection to peer {}: {}", addr, e);
+        }
+        self.remove_peer(peer_id);
+        true
+    }
+
     /// Connect to a peer, and register this peer
     fn connect(&mut self, addr: &std::net::SocketAddr) -> std::io::Result<peer::Handle> {
         // we need to estabilsh a stdlib tcp stream, since we need it to block
@@ -137,6 +200,26 @@ impl Context {
                     self.peers[*peer_id].handle.write(msg.clone());
                 }
             }
+            ControlSignal::SendMessage(addr, msg) => {
+                trace!("Processing SendMessage command");
+                match self.peer_list.iter().find(|peer_id| self.peers[**peer_id].addr == addr) {
+                    Some(peer_id) => self.peers[*peer_id].handle.write(msg),
+                    None => debug!("Dropping message to disconnected peer {}", addr),
+                }
+            }
+            ControlSignal::ListPeers(result_chan) => {
+                trace!("Processing ListPeers command");
+                result_chan.send(self.peer_infos()).unwrap();
+            }
+            ControlSignal::DisconnectPeer(req) => {
+                trace!("Processing DisconnectPeer command");
+                let disconnected = self.disconnect(&req.addr);
+                req.result_chan.send(disconnected).unwrap();
+            }
+            ControlSignal::Subscribe(subscriber) => {
+                trace!("Processing Subscribe command");
+                self.event_subscribers.push(subscriber);
+            }
         }
         Ok(())
     }
@@ -164,9 +247,7 @@ impl Context {
                 Ok(ReadResult::EOF) => {
                     // EOF, remove it from the connections set
                     info!("Peer {} dropped connection", peer.addr);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                     break;
                 }
                 Ok(ReadResult::Continue) => {
@@ -187,9 +268,7 @@ impl Context {
                         break;
                     } else {
                         warn!("Error reading peer {}, disconnecting: {}", peer.addr, e);
-                        self.peers.remove(peer_id);
-                        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                        self.peer_list.swap_remove(index);
+                        self.remove_peer(peer_id);
                         break;
                     }
                 }
@@ -224,9 +303,7 @@ impl Context {
             Ok(WriteResult::EOF) => {
                 // EOF, remove it from the connections set
                 info!("Peer {} dropped connection", peer.addr);
-                self.peers.remove(peer_id);
-                let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                self.peer_list.swap_remove(index);
+                self.remove_peer(peer_id);
             }
             Ok(WriteResult::ChanClosed) => {
                 // the channel is closed. no more writes.
@@ -246,9 +323,7 @@ impl Context {
                 // socket is not ready anymore, stop reading
                 } else {
                     warn!("Error writing peer {}, disconnecting: {}", peer.addr, e);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                 }
             }
         }
@@ -261,7 +336,7 @@ impl Context {
         let server = net::TcpListener::bind(&self.addr)?;
 
         // token for new incoming connection
-        const INCOMING: mio::Token = mio::Token(std::usize::MAX - 1);
+        const INCOMING: mio::Token = mio::Token(usize::MAX - 1);
         self.poll.register(
             &server,
             INCOMING,
@@ -270,7 +345,7 @@ impl Context {
         )?;
 
         // token for new control signal from the handle
-        const CONTROL: mio::Token = mio::Token(std::usize::MAX - 2);
+        const CONTROL: mio::Token = mio::Token(usize::MAX - 2);
         self.poll.register(
             &self.control_chan,
             CONTROL,
@@ -353,6 +428,9 @@ impl Context {
                             }
                             1 => {
                                 trace!("Peer {} outgoing queue readable", peer_id);
+                                if !self.peers.contains(peer_id) {
+                                    continue;
+                                }
                                 self.register_write_interest(peer_id)?;
                             }
                             _ => unreachable!(),
@@ -387,14 +465,76 @@ impl Handle {
             .send(ControlSignal::BroadcastMessage(msg))
             .unwrap();
     }
+
+    /// Send a message to a single peer. The message is dropped if the peer is not connected.
+    pub fn send(&self, addr: std::net::SocketAddr, msg: message::Message) {
+        self.control_chan
+            .send(ControlSignal::SendMessage(addr, msg))
+            .unwrap();
+    }
+
+    /// List the peers that are currently connected.
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let (sender, receiver) = cbchannel::unbounded();
+        self.control_chan
+            .send(ControlSignal::ListPeers(sender))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+
+    /// Disconnect the peer at `addr`. Returns `false` if no such peer is connected.
+    pub fn disconnect(&self, addr: std::net::SocketAddr) -> bool {
+        let (sender, receiver) = cbchannel::unbounded();
+        let request = DisconnectRequest {
+            addr,
+            result_chan: sender,
+        };
+        self.control_chan
+            .send(ControlSignal::DisconnectPeer(request))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+
+    /// Subscribe to peer connect/disconnect events. Events that happen before the subscription
+    /// is processed by the server are not delivered.
+    pub fn subscribe(&self) -> cbchannel::Receiver<PeerEvent> {
+        let (sender, receiver) = cbchannel::unbounded();
+        self.control_chan
+            .send(ControlSignal::Subscribe(sender))
+            .unwrap();
+        receiver
+    }
+}
+
+/// A connected peer, as seen by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PeerInfo {
+    pub addr: std::net::SocketAddr,
+    pub direction: peer::Direction,
+}
+
+/// Connection lifecycle events delivered to subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    Connected(PeerInfo),
+    Disconnected(PeerInfo),
 }
 
 enum ControlSignal {
     ConnectNewPeer(ConnectRequest),
     BroadcastMessage(message::Message),
+    SendMessage(std::net::SocketAddr, message::Message),
+    ListPeers(cbchannel::Sender<Vec<PeerInfo>>),
+    DisconnectPeer(DisconnectRequest),
+    Subscribe(cbchannel::Sender<PeerEvent>),
 }
 
 struct ConnectRequest {
     addr: std::net::SocketAddr,
     result_chan: cbchannel::Sender<std::io::Result<peer::Handle>>,
 }
+
+struct DisconnectRequest {
+    addr: std::net::SocketAddr,
+    result_chan: cbchannel::Sender<bool>,
+}
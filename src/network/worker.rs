@@ -1,43 +1,79 @@
 use super::message::Message;
 use super::peer;
-use crate::address::H160;
 use crate::network::server::Handle as ServerHandle;
+use crate::network::keepalive::Handle as KeepaliveHandle;
+use crate::network::inventory::Handle as InventoryHandle;
 use crate::blockchain::{Blockchain, BlockOrigin};
-use crate::block::Block;
+use crate::network::server::PeerEvent;
+use crate::block::{short_id, Block, CompactBlock};
 use crate::crypto::hash::{Hashable, H256};
-use crate::transaction::SignedTransaction as Transaction;
-use crate::mempool::Mempool;
+use crate::transaction::{verify_batch, SignedTransaction as Transaction};
+use crate::mempool::{EvictionReason, Mempool, TransactionError};
+use crate::metrics::metrics;
+use crate::spv::InclusionProof;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use crossbeam::channel;
 use log::{debug, warn};
 
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the missing transactions of a compact block before requesting the full
+/// block from another peer.
+const PENDING_BLOCK_TIMEOUT_MS: u64 = 5000;
 
 #[derive(Clone)]
 pub struct Context {
     msg_chan: channel::Receiver<(Vec<u8>, peer::Handle)>,
     num_worker: usize,
     server: ServerHandle,
+    keepalive: KeepaliveHandle,
+    inventory: InventoryHandle,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
+    /// Compact blocks waiting for missing transactions, by block hash
+    pending_blocks: Arc<Mutex<HashMap<H256, PendingBlock>>>,
+}
+
+/// A compact block that could not be rebuilt from the mempool alone
+struct PendingBlock {
+    compact: CompactBlock,
+    /// The peer we requested the missing transactions from
+    peer: SocketAddr,
+    requested_at: Instant,
 }
 
 pub fn new(
     num_worker: usize,
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
     server: &ServerHandle,
+    keepalive: &KeepaliveHandle,
+    inventory: &InventoryHandle,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
 ) -> Context {
     Context {
         msg_chan: msg_src,
-        num_worker: num_worker,
+        num_worker,
         server: server.clone(),
-        blockchain: blockchain,
-        mempool: mempool,
+        keepalive: keepalive.clone(),
+        inventory: inventory.clone(),
+        blockchain,
+        mempool,
+        pending_blocks: Arc::new(Mutex::new(HashMap::new())),
     }
 }
 
+/// Milliseconds between the creation of a block and now
+fn since_creation(block: &Block) -> u128 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+    now.as_millis().saturating_sub(block.header.timestamp)
+}
+
 impl Context {
     pub fn start(self) {
         let num_worker = self.num_worker;
@@ -48,6 +84,94 @@ impl Context {
                 warn!("Worker thread {} exited", i);
             });
         }
+        let events = self.server.subscribe();
+        thread::spawn(move || {
+            self.pending_blocks_loop(events);
+            warn!("Worker pending block thread exited");
+        });
+    }
+
+    /// Give up on compact blocks whose missing transactions do not arrive in time, or whose peer
+    /// disconnects, and request the full blocks from another peer.
+    fn pending_blocks_loop(&self, events: channel::Receiver<PeerEvent>) {
+        let timeout = Duration::from_millis(PENDING_BLOCK_TIMEOUT_MS);
+        let interval = timeout / 5;
+        let mut next_check = Instant::now() + interval;
+        loop {
+            let wait = next_check.saturating_duration_since(Instant::now());
+            channel::select! {
+                recv(events) -> event => match event {
+                    Ok(PeerEvent::Disconnected(info)) => {
+                        self.refetch_pending_blocks(|block| block.peer == info.addr);
+                    }
+                    Ok(PeerEvent::Connected(_)) => {}
+                    Err(_) => return,
+                },
+                default(wait) => {
+                    self.refetch_pending_blocks(|block| block.requested_at.elapsed() >= timeout);
+                    next_check = Instant::now() + interval;
+                }
+            }
+        }
+    }
+
+    /// Drop the pending blocks matching `give_up` and request each in full from another peer.
+    fn refetch_pending_blocks<F>(&self, give_up: F)
+    where
+        F: Fn(&PendingBlock) -> bool,
+    {
+        let mut dropped = Vec::new();
+        self.pending_blocks.lock().unwrap().retain(|hash, block| {
+            if give_up(block) {
+                dropped.push((*hash, block.peer));
+                false
+            } else {
+                true
+            }
+        });
+        for (hash, failed) in dropped {
+            self.request_from_another_peer(hash, failed);
+        }
+    }
+
+    /// Request the full block `hash` from a peer other than `failed`, which could not deliver it.
+    fn request_from_another_peer(&self, hash: H256, failed: SocketAddr) {
+        match self.server.peers().iter().find(|info| info.addr != failed) {
+            Some(info) => {
+                debug!("Requesting block {:?} from {} instead of {}", hash, info.addr, failed);
+                self.server.send(info.addr, Message::GetBlocks(vec![hash]));
+            }
+            None => debug!("Dropping pending compact block {:?}: no other peer to ask", hash),
+        }
+    }
+
+    /// Rebuild a compact block from the mempool and the `extra` transactions. The result is only
+    /// accepted if it hashes to the announced `hash` and the transactions match the header's
+    /// Merkle root, which guards against short ID collisions.
+    fn reconstruct_block(
+        &self,
+        hash: &H256,
+        compact: &CompactBlock,
+        extra: &[Transaction],
+    ) -> Result<Block, Vec<u32>> {
+        let extra: HashMap<u64, &Transaction> =
+            extra.iter().map(|tx| (short_id(hash, &tx.hash()), tx)).collect();
+        let block = {
+            let mempool = self.mempool.lock().unwrap();
+            let index = mempool.short_id_index(hash);
+            compact.reconstruct(|id| match extra.get(&id) {
+                Some(tx) => Some((*tx).clone()),
+                None => index
+                    .get(&id)
+                    .and_then(|tx_hash| mempool.get_transaction(tx_hash))
+                    .cloned(),
+            })?
+        };
+        if block.hash() != *hash || !block.has_valid_merkle_root() {
+            warn!("Compact block {:?} reconstructed to a different block", hash);
+            return Err(vec![]);
+        }
+        Ok(block)
     }
 
 
@@ -67,28 +191,95 @@ impl Context {
         for orphan in found_orphans {
             // assert that the parent block is already in the blockchain
             assert!(self.blockchain.lock().unwrap().contains_block(&orphan.header.parent));
-            self.blockchain.lock().unwrap().insert(&orphan);
+            let origin = BlockOrigin::Received { delay_ms: since_creation(&orphan) };
+            self.blockchain.lock().unwrap().insert_with_origin(&orphan, origin);
 
             // remove the doubly-spent transactions found by changed state from mempool
             let hashes: Vec<H256> = orphan.content.transactions.iter().map(|tx| tx.hash()).collect();
-            self.mempool.lock().unwrap().remove_transactions(&hashes);
+            self.mempool.lock().unwrap().remove_transactions(&hashes, EvictionReason::Included);
 
             self.handle_orphans(orphan);  // this orphan might also be a parent to some orphans, so we need to check recursively
         }
     }
 
+    /// Validate received blocks, insert them into the blockchain (or the orphan buffer) and
+    /// announce the accepted ones to other peers.
+    fn process_blocks(&self, blocks: Vec<Block>) {
+        let mut new_hashes = Vec::new();
+        for block in blocks {
+            if self.blockchain.lock().unwrap().contains_block(&block.hash()) {
+                continue;
+            }
+            // check if the block is valid before inserting it into blockchain
+            // 3.1. PoW validity check
+            // - `block.hash() <= difficulty`. (Note that difficulty is a misnomer here since a higher 'difficulty' here means that the block is easier to mine).
+            // - Difficulty in the block header is consistent with your view. We have a fixed mining difficulty for this project, thus, this would just involve checking if the difficulty equals the genesis block's difficulty.
+            
+            // If the check fails, it indicates that the block is corrupted or dishonest. You should ignore the block instead of adding it to your blockchain.
+            if block.hash() > Block::genesis().header.difficulty {  // failed PoW check
+                warn!("Invalid block detected: {:?}", block);
+                continue;
+            }
+            // The PoW only covers the header, so the transactions must match its Merkle root.
+            if !block.has_valid_merkle_root() {
+                warn!("Block {:?} does not match its Merkle root", block.hash());
+                continue;
+            }
+
+            // propagate valid blocks (even for orphan blocks, we need to propagate them to other peers, so that we could ask the other peers to find the parent block)
+            new_hashes.push(block.hash());
+
+            // 3.2. Parent block existence check
+            // - Check if the block's parent exists in your local copy of your blockchain, if the parent exists, insert the block into your blockchain.
+            // - If this check fails, you need to add the block in an 'orphan buffer'. The buffer stores the blocks whose parent is not seen yet. Also, you need to send **GetBlocks** message, containing this parent hash.
+
+            let parent_hash = block.header.parent;
+            if self.blockchain.lock().unwrap().contains_block(&parent_hash) {
+                // the delay between the creation of the block and now
+                let origin_received = BlockOrigin::Received { delay_ms: since_creation(&block) };
+                self.blockchain.lock().unwrap().insert_with_origin(&block, origin_received);  // insert the block into your blockchain
+                
+                // remove the doubly-spent transactions found by changed state from mempool
+                let hashes: Vec<H256> = block.content.transactions.iter().map(|tx| tx.hash()).collect();
+                self.mempool.lock().unwrap().remove_transactions(&hashes, EvictionReason::Included);
+                // 3.3. Orphan block handler: this block might be a parent to some orphans
+                self.handle_orphans(block.clone());
+            } else {
+                warn!("Orphan block detected: {:?}", block);
+                self.blockchain.lock().unwrap().insert_orphan(parent_hash, block.clone());
+                self.server.broadcast(Message::GetBlocks(vec![parent_hash]));  // to look for this orphan's parent; maybe it is in other peers
+            }
+            
+            
+            
+            if self.blockchain.lock().unwrap().tip() == block.hash() {
+                // if the block is added to the tip of the longest chain:
+                debug!("Block inserted into blockchain (at tip) at height: {:?} with hash: {:?}", self.blockchain.lock().unwrap().get_length(&block.hash()), block.hash());
+            }
+            debug!("# blocks in blockchain: {:?}", self.blockchain.lock().unwrap().len());
+            debug!("Height of the tip of the blockchain: {:?}", self.blockchain.lock().unwrap().length_of_longest_chain());
+
+        }
+        if !new_hashes.is_empty() {
+            self.inventory.announce_blocks(new_hashes);  // propagate the new block hashes to peers that don't have them yet
+        }
+    }
+
     fn worker_loop(&self) {
         loop {
             let msg = self.msg_chan.recv().unwrap();
             let (msg, peer) = msg;
+            let bytes = msg.len();
             let msg: Message = bincode::deserialize(&msg).unwrap();
+            metrics().message_received(&msg, bytes);
             match msg {
                 Message::Ping(nonce) => {
                     debug!("Ping: {}", nonce);
-                    peer.write(Message::Pong(nonce.to_string()));
+                    peer.write(Message::Pong(nonce));
                 }
                 Message::Pong(nonce) => {
                     debug!("Pong: {}", nonce);
+                    self.keepalive.pong(peer.addr(), nonce);
                 }
                 // 1. NewBlockHashes(Vec\<H256\>), similar to *inv* in lectures
                 // 2. GetBlocks(Vec\<H256\>), similar to *getdata* in lectures
@@ -100,14 +291,19 @@ impl Context {
                     // Upon receiving **NewBlockHashes**, if the hashes are not already in blockchain, you need to ask for them by sending **GetBlocks**.
                     
                     debug!("Message::NewBlockHashes: {:?}", hashes);
+                    self.inventory.mark_known(peer.addr(), hashes.clone());
                     let mut new_hashes = Vec::new();
                     for hash in hashes {
                         if !self.blockchain.lock().unwrap().contains_block(&hash) {
                             new_hashes.push(hash);
                         }
                     }
+                    // ask the announcing peer for compact blocks, unless we are already rebuilding them
+                    let pending = self.pending_blocks.lock().unwrap();
+                    new_hashes.retain(|hash| !pending.contains_key(hash));
+                    drop(pending);
                     if !new_hashes.is_empty() {
-                        self.server.broadcast(Message::GetBlocks(new_hashes.clone()));
+                        peer.write(Message::GetCompactBlocks(new_hashes));
                     }
                 }
                 Message::GetBlocks(hashes) => {
@@ -123,7 +319,7 @@ impl Context {
 
                     if !blocks.is_empty() {
                         // println!("Get Blocks: {:?}", blocks);
-                        self.server.broadcast(Message::Blocks(blocks));
+                        peer.write(Message::Blocks(blocks));
                     }
                 }
                 Message::Blocks(blocks) => {
@@ -131,68 +327,81 @@ impl Context {
                     //- Check if each block is already in the blockchain. If so, skip that block; otherwise, check if that block is valid before inserting it into blockchain. We will discuss the validity checks in the following subsections.
                     //- Finally, you need to broadcast **NewBlockHashes** message when receiving new blocks in **Blocks** message. **NewBlockHashes** message should contain hashes of blocks newly received and accepted.
                     debug!("Message::Blocks");
-                    // print out the time when the new block hashes are received
-                    let now = std::time::SystemTime::now();
-                    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
-                    // println!("Received new block hashes at: {:?}", since_the_epoch);
-                    // print out the delay between the time when the new block hashes are received and the time when the new block is created
-                    let delay = since_the_epoch.as_millis() - blocks[0].clone().header.timestamp;
-                    // println!("Block creation time (milliseconds): {:?}", blocks[0].clone().header.timestamp);
-                    // println!("Received new block hashes at: {:?}", since_the_epoch.as_millis());
-                    // println!("Delay (milliseconds): {:?}", delay);
-                    let mut new_hashes = Vec::new();
-                    for block in blocks {
-                        if self.blockchain.lock().unwrap().contains_block(&block.hash()) {
-                            continue;
-                        }
-                        // check if the block is valid before inserting it into blockchain
-                        // 3.1. PoW validity check
-                        // - `block.hash() <= difficulty`. (Note that difficulty is a misnomer here since a higher 'difficulty' here means that the block is easier to mine).
-                        // - Difficulty in the block header is consistent with your view. We have a fixed mining difficulty for this project, thus, this would just involve checking if the difficulty equals the genesis block's difficulty.
-                        
-                        // If the check fails, it indicates that the block is corrupted or dishonest. You should ignore the block instead of adding it to your blockchain.
-                        if block.hash() > Block::genesis().header.difficulty {  // failed PoW check
-                            warn!("Invalid block detected: {:?}", block);
+                    self.inventory.mark_known(peer.addr(), blocks.iter().map(|block| block.hash()).collect());
+                    self.process_blocks(blocks);
+                }
+                Message::GetCompactBlocks(hashes) => {
+                    debug!("Message::GetCompactBlocks: {:?}", hashes);
+                    let compacts: Vec<(H256, CompactBlock)> = self
+                        .blockchain
+                        .lock()
+                        .unwrap()
+                        .get_blocks(&hashes)
+                        .iter()
+                        .map(|block| (block.hash(), block.compact()))
+                        .collect();
+                    if !compacts.is_empty() {
+                        peer.write(Message::CompactBlocks(compacts));
+                    }
+                }
+                Message::CompactBlocks(compacts) => {
+                    // Rebuild each block from the mempool. Missing transactions are requested from
+                    // the sender, and blocks that cannot be rebuilt are requested in full.
+                    debug!("Message::CompactBlocks");
+                    self.inventory.mark_known(peer.addr(), compacts.iter().map(|(hash, _)| *hash).collect());
+                    let mut blocks = Vec::new();
+                    for (hash, compact) in compacts {
+                        if self.blockchain.lock().unwrap().contains_block(&hash)
+                            || self.pending_blocks.lock().unwrap().contains_key(&hash)
+                        {
                             continue;
                         }
-
-                        // propagate valid blocks (even for orphan blocks, we need to propagate them to other peers, so that we could ask the other peers to find the parent block)
-                        new_hashes.push(block.hash().clone());
-
-                        // 3.2. Parent block existence check
-                        // - Check if the block's parent exists in your local copy of your blockchain, if the parent exists, insert the block into your blockchain.
-                        // - If this check fails, you need to add the block in an 'orphan buffer'. The buffer stores the blocks whose parent is not seen yet. Also, you need to send **GetBlocks** message, containing this parent hash.
-
-                        let parent_hash = block.header.parent;
-                        if self.blockchain.lock().unwrap().contains_block(&parent_hash) {
-                            self.blockchain.lock().unwrap().insert(&block);  // insert the block into your blockchain
-                            
-                            // remove the doubly-spent transactions found by changed state from mempool
-                            let hashes: Vec<H256> = block.content.transactions.iter().map(|tx| tx.hash()).collect();
-                            self.mempool.lock().unwrap().remove_transactions(&hashes);
-                            // 3.3. Orphan block handler: this block might be a parent to some orphans
-                            self.handle_orphans(block.clone());
-                        } else {
-                            warn!("Orphan block detected: {:?}", block);
-                            self.blockchain.lock().unwrap().insert_orphan(parent_hash, block.clone());
-                            self.server.broadcast(Message::GetBlocks(vec![parent_hash]));  // to look for this orphan's parent; maybe it is in other peers
-                        }
-                        
-                        // set the delay time for each block
-                        let origin_received = BlockOrigin::Received { delay_ms: delay };
-                        self.blockchain.lock().unwrap().hash_to_origin.insert(block.hash(), origin_received);
-                        
-                        
-                        if self.blockchain.lock().unwrap().tip() == block.hash() {
-                            // if the block is added to the tip of the longest chain:
-                            debug!("Block inserted into blockchain (at tip) at height: {:?} with hash: {:?}", self.blockchain.lock().unwrap().get_length(&block.hash()), block.hash());
+                        match self.reconstruct_block(&hash, &compact, &[]) {
+                            Ok(block) => blocks.push(block),
+                            Err(missing) if !missing.is_empty() => {
+                                debug!("Compact block {:?} misses {} transactions", hash, missing.len());
+                                self.pending_blocks.lock().unwrap().insert(
+                                    hash,
+                                    PendingBlock {
+                                        compact,
+                                        peer: peer.addr(),
+                                        requested_at: Instant::now(),
+                                    },
+                                );
+                                peer.write(Message::GetBlockTransactions(hash, missing));
+                            }
+                            Err(_) => peer.write(Message::GetBlocks(vec![hash])),
                         }
-                        debug!("# blocks in blockchain: {:?}", self.blockchain.lock().unwrap().len());
-                        debug!("Height of the tip of the blockchain: {:?}", self.blockchain.lock().unwrap().length_of_longest_chain());
-
                     }
-                    if !new_hashes.is_empty() {
-                        self.server.broadcast(Message::NewBlockHashes(new_hashes.clone()));  // propagate the new block hashes to other peers
+                    self.process_blocks(blocks);
+                }
+                Message::GetBlockTransactions(hash, indexes) => {
+                    // Always answer, with no transactions if we do not know the block, so that
+                    // the requester does not wait for a timeout.
+                    debug!("Message::GetBlockTransactions: {:?}", hash);
+                    let transactions: Vec<Transaction> = match self.blockchain.lock().unwrap().get_block(&hash) {
+                        Some(block) => indexes
+                            .iter()
+                            .filter_map(|i| block.content.transactions.get(*i as usize).cloned())
+                            .collect(),
+                        None => vec![],
+                    };
+                    peer.write(Message::BlockTransactions(hash, transactions));
+                }
+                Message::BlockTransactions(hash, transactions) => {
+                    debug!("Message::BlockTransactions: {:?}", hash);
+                    let pending = match self.pending_blocks.lock().unwrap().remove(&hash) {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+                    match self.reconstruct_block(&hash, &pending.compact, &transactions) {
+                        Ok(block) => self.process_blocks(vec![block]),
+                        // the peer does not have the block (anymore)
+                        Err(_) if transactions.is_empty() => self.request_from_another_peer(hash, peer.addr()),
+                        Err(_) => {
+                            debug!("Falling back to the full block {:?}", hash);
+                            peer.write(Message::GetBlocks(vec![hash]));
+                        }
                     }
                 }
                 Message::NewTransactionHashes(hashes) => {
@@ -201,6 +410,7 @@ impl Context {
 
                     // Upon receiving **NewTransactionHashes**, if the hashes are not already in mempool, you need to ask for them by sending **GetTransactions**.
                     debug!("Message::NewTransactionHashes: {:?}", hashes);
+                    self.inventory.mark_known(peer.addr(), hashes.clone());
                     let mut new_hashes = Vec::new();
                     for hash in hashes {
                         if !self.mempool.lock().unwrap().contains_transaction(&hash) {
@@ -208,7 +418,7 @@ impl Context {
                         }
                     }
                     if !new_hashes.is_empty() {
-                        self.server.broadcast(Message::GetTransactions(new_hashes.clone()));
+                        peer.write(Message::GetTransactions(new_hashes));
                     }
                 }
 
@@ -222,7 +432,7 @@ impl Context {
                         .unwrap()
                         .get_transactions(&hashes);
                     if !transactions.is_empty() {
-                        self.server.broadcast(Message::Transactions(transactions));
+                        peer.write(Message::Transactions(transactions));
                     }
                 }
 
@@ -231,55 +441,58 @@ impl Context {
                     //- Check if each transaction is already in the mempool. If so, skip that transaction; otherwise, check if that transaction is valid before inserting it into the mempool. 
                     //- Finally, you need to broadcast **NewTransactionHashes** message when receiving new transactions in **Transactions** message. **NewTransactionHashes** message should contain hashes of transactions newly received and accepted.
                     debug!("Message::Transactions");
+                    self.inventory.mark_known(peer.addr(), transactions.iter().map(|tx| tx.hash()).collect());
                     let mut new_hashes = Vec::new();
-                    for transaction in transactions {
+                    // 4.1. verify all signatures together, before taking any lock
+                    let signatures_valid = verify_batch(&transactions);
+                    for (transaction, signature_valid) in transactions.into_iter().zip(signatures_valid) {
                         if self.mempool.lock().unwrap().contains_transaction(&transaction.hash()) {
                             continue;
                         }
-                        // check if the transaction is valid before inserting it into blockchain
-                        // 4.1. Signature validity check
-                        // - Verify the signature of the transaction using the public key of the sender. If the signature is valid, the transaction is valid.
-                        // - If the signature is invalid, the transaction is corrupted or dishonest. You should ignore the transaction instead of adding it to your blockchain.
-                        if !transaction.verify_signature() {
-                            // warn!("Invalid transaction detected: {:?}", transaction);
-                            warn!("Invalid transaction detected: Failed to verify signature");
-                            continue;
-                        }
-
-                        // 4.2 In the account-based model, check if the public key matches the owner's address of the withdrawing account. (This step needs struct **State**, see below.)
-                        // - If the public key does not match the owner's address, the transaction is corrupted or dishonest. You should ignore the transaction instead of adding it to your blockchain.
-                        // - If the public key matches the owner's address, the transaction is valid.
-                        if transaction.raw.from_addr != H160::from_pubkey(&transaction.pub_key) {
-                            warn!("Invalid transaction detected: Failed to match from_addr: {:?} with address of pub_key: {:?}", transaction.raw.from_addr, H160::from_pubkey(&transaction.pub_key));
+                        if !signature_valid {
+                            warn!("P2P Node Received An Invalid transaction: {}", TransactionError::InvalidSignature);
                             continue;
                         }
-                        // 4.3 Double spend checks:
-                        // - In the account-based model, check if the balance is enough and the suggested account nonce is equal to one plus the account nonce. This check also needs **State** (see below).
-                        
-                        let sender = H160::from_pubkey(&transaction.pub_key);
-                        let block_hash = self.blockchain.lock().unwrap().tip();  // tip of the blockchain
-                        // get the sender's nonce and balance
+                        // check if the transaction is valid before inserting it into the mempool:
+                        // 4.2. owner's address, 4.3. double spend (nonce and balance) against the tip state and
+                        // the pending transactions of the sender
                         let blockchain = self.blockchain.lock().unwrap();
-                        let (sender_account_nonce, sender_account_balance) = blockchain.hash_to_state.get(&block_hash).unwrap().get(&sender).unwrap();
-                        // check if the nonce (sender) is correct
-                        if sender_account_nonce + 1 != transaction.raw.nonce {
-                            warn!("P2P Node Received An Invalid transaction detected: tx's nonce: {:?} should be 1 more than sender_account_nonce: {:?}", transaction.raw.nonce, sender_account_nonce);
-                            continue;
-                        }
-                        // check if the balance is enough
-                        if sender_account_balance < &transaction.raw.value {
-                            warn!("Invalid transaction detected: Sender account doesn't have enough balance with value: {:?} and sender_account_balance: {:?}", transaction.raw.value, sender_account_balance);
+                        let tip_state = &blockchain.hash_to_state[&blockchain.tip()];
+                        if let Err(e) = self.mempool.lock().unwrap().validate_pending_verified(tip_state, &transaction) {
+                            warn!("P2P Node Received An Invalid transaction: {}", e);
                             continue;
                         }
 
-                        new_hashes.push(transaction.hash().clone());
+                        new_hashes.push(transaction.hash());
                         self.mempool.lock().unwrap().insert(transaction.clone());
                         debug!("Transaction inserted into mempool: {:?}", transaction);
                         debug!("# Hashes in mempool: {:?}", self.mempool.lock().unwrap().len());
                     }
                     // propagate valid transactions
                     if !new_hashes.is_empty() {
-                        self.server.broadcast(Message::NewTransactionHashes(new_hashes.clone()));  // propagate the new transaction hashes to other peers
+                        self.inventory.announce_transactions(new_hashes);  // propagate the new transaction hashes to peers that don't have them yet
+                    }
+                }
+                Message::GetMerkleProofs(hashes) => {
+                    // Answer with the proofs of the transactions confirmed on our longest chain.
+                    debug!("Message::GetMerkleProofs: {:?}", hashes);
+                    let proofs: Vec<InclusionProof> = {
+                        let blockchain = self.blockchain.lock().unwrap();
+                        hashes.iter().filter_map(|hash| blockchain.inclusion_proof(hash)).collect()
+                    };
+                    if !proofs.is_empty() {
+                        peer.write(Message::MerkleProofs(proofs));
+                    }
+                }
+                Message::MerkleProofs(proofs) => {
+                    // A full node has the blocks already; SPV clients check proofs with a
+                    // `HeaderChain`.
+                    debug!("Message::MerkleProofs: {} proofs", proofs.len());
+                    let blockchain = self.blockchain.lock().unwrap();
+                    for proof in proofs {
+                        if !proof.verify_path() || !blockchain.contains_block(&proof.header.hash()) {
+                            warn!("Invalid Merkle proof of transaction {:?}", proof.tx_hash);
+                        }
                     }
                 }
             }
@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::block::{Block, Header};
+use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::merkle::{self, MerkleTree};
+
+// Simplified payment verification: a client that only follows block headers can check that a
+// transaction is in a block of the longest chain, given the block's header and the Merkle path
+// of the transaction. It trusts the chain with the most work, not the validity of its blocks.
+
+/// A proof that a transaction is in a block: the block's header and the Merkle path from the
+/// transaction hash to the header's Merkle root.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InclusionProof {
+    pub header: Header,
+    pub tx_hash: H256,
+    /// The position of the transaction in the block
+    pub index: u32,
+    /// The number of transactions in the block
+    pub leaf_count: u32,
+    /// Sibling hashes from the leaf up to the root
+    pub path: Vec<H256>,
+}
+
+impl InclusionProof {
+    /// The proof of `tx_hash` in `block`, or `None` if the block does not contain it.
+    pub fn new(block: &Block, tx_hash: &H256) -> Option<Self> {
+        let transactions = &block.content.transactions;
+        let index = transactions.iter().position(|tx| tx.hash() == *tx_hash)?;
+        Some(InclusionProof {
+            header: block.header.clone(),
+            tx_hash: *tx_hash,
+            index: index as u32,
+            leaf_count: transactions.len() as u32,
+            path: MerkleTree::new(transactions).proof(index),
+        })
+    }
+
+    /// Whether the path links the transaction to the Merkle root of the header.
+    pub fn verify_path(&self) -> bool {
+        self.index < self.leaf_count
+            && merkle::verify(
+                &self.header.merkle_root,
+                &self.tx_hash,
+                &self.path,
+                self.index as usize,
+                self.leaf_count as usize,
+            )
+    }
+}
+
+/// Reasons a header or an inclusion proof is rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpvError {
+    /// The parent of the header is not in the chain
+    UnknownParent(H256),
+    /// The header has another difficulty than the chain
+    WrongDifficulty(H256),
+    /// The header hash is above its difficulty
+    InsufficientWork(H256),
+    /// The block of the proof is not in the chain
+    UnknownBlock(H256),
+    /// The block of the proof is on a fork, not on the longest chain
+    NotInLongestChain(H256),
+    /// The Merkle path does not lead to the root of the header
+    InvalidPath,
+}
+
+impl std::fmt::Display for SpvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SpvError::UnknownParent(hash) => write!(f, "unknown parent {}", hash),
+            SpvError::WrongDifficulty(hash) => write!(f, "header {} has the wrong difficulty", hash),
+            SpvError::InsufficientWork(hash) => write!(f, "header {} is above its difficulty", hash),
+            SpvError::UnknownBlock(hash) => write!(f, "unknown block {}", hash),
+            SpvError::NotInLongestChain(hash) => write!(f, "block {} is not in the longest chain", hash),
+            SpvError::InvalidPath => write!(f, "the Merkle path does not match the header"),
+        }
+    }
+}
+
+/// The headers of a blockchain, as followed by an SPV client. Like the full node, it uses the
+/// fixed difficulty of the genesis block and follows the longest chain.
+pub struct HeaderChain {
+    /// Headers with their height
+    headers: HashMap<H256, (Header, u64)>,
+    tip: H256,
+    difficulty: H256,
+}
+
+impl HeaderChain {
+    /// A chain of only the `genesis` header.
+    pub fn new(genesis: Header) -> Self {
+        let hash = genesis.hash();
+        let difficulty = genesis.difficulty;
+        let mut headers = HashMap::new();
+        headers.insert(hash, (genesis, 0));
+        HeaderChain { headers, tip: hash, difficulty }
+    }
+
+    /// Add a header after checking its proof of work, and return its hash. Headers must be added
+    /// parents first.
+    pub fn insert(&mut self, header: Header) -> Result<H256, SpvError> {
+        let hash = header.hash();
+        let parent_height = match self.headers.get(&header.parent) {
+            Some((_, height)) => *height,
+            None => return Err(SpvError::UnknownParent(header.parent)),
+        };
+        if header.difficulty != self.difficulty {
+            return Err(SpvError::WrongDifficulty(hash));
+        }
+        if hash > header.difficulty {
+            return Err(SpvError::InsufficientWork(hash));
+        }
+        let height = parent_height + 1;
+        if height > self.height() {
+            self.tip = hash;
+        }
+        self.headers.insert(hash, (header, height));
+        Ok(hash)
+    }
+
+    pub fn tip(&self) -> H256 {
+        self.tip
+    }
+
+    /// The height of the tip
+    pub fn height(&self) -> u64 {
+        self.headers[&self.tip].1
+    }
+
+    /// Whether the header `hash` is an ancestor of the tip, or the tip itself.
+    fn in_longest_chain(&self, hash: &H256, height: u64) -> bool {
+        let mut current = self.tip;
+        for _ in height..self.height() {
+            current = self.headers[&current].0.parent;
+        }
+        current == *hash
+    }
+
+    /// Check that the transaction of `proof` is in a block of the longest chain, and return the
+    /// number of confirmations, i.e. the number of blocks from that block to the tip.
+    pub fn verify(&self, proof: &InclusionProof) -> Result<u64, SpvError> {
+        let hash = proof.header.hash();
+        let height = match self.headers.get(&hash) {
+            Some((_, height)) => *height,
+            None => return Err(SpvError::UnknownBlock(hash)),
+        };
+        if !self.in_longest_chain(&hash, height) {
+            return Err(SpvError::NotInLongestChain(hash));
+        }
+        if !proof.verify_path() {
+            return Err(SpvError::InvalidPath);
+        }
+        Ok(self.height() - height + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_block;
+    use crate::transaction::SignedTransaction;
+
+    /// A block whose header meets its difficulty.
+    fn mine(parent: &H256, transactions: Vec<SignedTransaction>) -> Block {
+        let mut block = generate_block(parent, transactions);
+        while block.hash() > block.header.difficulty {
+            block.header.nonce = block.header.nonce.wrapping_add(1);
+        }
+        block
+    }
+
+    fn transactions(count: u64) -> Vec<SignedTransaction> {
+        (0..count)
+            .map(|value| {
+                let mut tx = SignedTransaction::default();
+                tx.raw.value = value;
+                tx
+            })
+            .collect()
+    }
+
+    #[test]
+    fn proofs_of_every_position() {
+        for count in 1..=5 {
+            let block = mine(&Default::default(), transactions(count));
+            for tx in &block.content.transactions {
+                let proof = InclusionProof::new(&block, &tx.hash()).unwrap();
+                assert!(proof.verify_path(), "{} of {}", proof.index, count);
+            }
+        }
+        let block = mine(&Default::default(), transactions(2));
+        assert!(InclusionProof::new(&block, &H256::default()).is_none());
+    }
+
+    #[test]
+    fn verify_against_headers() {
+        let genesis = Block::genesis();
+        let mut chain = HeaderChain::new(genesis.header.clone());
+        let block_1 = mine(&genesis.hash(), transactions(3));
+        let block_2 = mine(&block_1.hash(), transactions(1));
+        let fork_1 = mine(&genesis.hash(), transactions(2));
+        assert_eq!(chain.insert(block_1.header.clone()), Ok(block_1.hash()));
+        assert_eq!(chain.insert(fork_1.header.clone()), Ok(fork_1.hash()));
+        assert_eq!(chain.insert(block_2.header.clone()), Ok(block_2.hash()));
+        assert_eq!(chain.tip(), block_2.hash());
+
+        let tx = &block_1.content.transactions[2];
+        let proof = InclusionProof::new(&block_1, &tx.hash()).unwrap();
+        assert_eq!(chain.verify(&proof), Ok(2));
+
+        let mut forged = proof.clone();
+        forged.tx_hash = block_1.content.transactions[1].hash();
+        assert_eq!(chain.verify(&forged), Err(SpvError::InvalidPath));
+        let mut out_of_range = proof;
+        out_of_range.index = 3;
+        assert_eq!(chain.verify(&out_of_range), Err(SpvError::InvalidPath));
+
+        let on_fork = InclusionProof::new(&fork_1, &fork_1.content.transactions[0].hash()).unwrap();
+        assert_eq!(chain.verify(&on_fork), Err(SpvError::NotInLongestChain(fork_1.hash())));
+        let unknown = mine(&block_2.hash(), transactions(1));
+        let proof = InclusionProof::new(&unknown, &unknown.content.transactions[0].hash()).unwrap();
+        assert_eq!(chain.verify(&proof), Err(SpvError::UnknownBlock(unknown.hash())));
+    }
+
+    #[test]
+    fn headers_need_work_and_a_parent() {
+        let genesis = Block::genesis();
+        let mut chain = HeaderChain::new(genesis.header.clone());
+        let mut block = mine(&genesis.hash(), transactions(1));
+        while block.hash() <= block.header.difficulty {
+            block.header.nonce = block.header.nonce.wrapping_add(1);
+        }
+        assert_eq!(chain.insert(block.header.clone()), Err(SpvError::InsufficientWork(block.hash())));
+
+        let mut easy = block.header.clone();
+        easy.difficulty = [0xff; 32].into();
+        assert_eq!(chain.insert(easy.clone()), Err(SpvError::WrongDifficulty(easy.hash())));
+
+        let orphan = mine(&[1u8; 32].into(), transactions(1));
+        assert_eq!(chain.insert(orphan.header), Err(SpvError::UnknownParent([1u8; 32].into())));
+        assert_eq!(chain.height(), 0);
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Serialize,Deserialize};
 use ring::signature::{Ed25519KeyPair, Signature, KeyPair, EdDSAParameters, UnparsedPublicKey};
-use crate::crypto::hash::Hashable;
+use crate::crypto::hash::{Hashable, H256};
 use crate::address::H160;
+use crate::encoding::{Decode, DecodeError, Encode, Reader, ENCODING_VERSION};
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct RawTransaction {
@@ -9,21 +10,224 @@ pub struct RawTransaction {
     pub to_addr: H160,
     pub value: u64,
     pub nonce: u32,
+    /// The chain the transaction is valid on, see `Blockchain::chain_id`
+    pub chain_id: H256,
+    /// The last block height at which the transaction may be included, if any
+    pub expiry_height: Option<u64>,
+}
+
+/// Prefix of every signed payload. It is versioned so that signatures over a future encoding can
+/// never be mistaken for signatures over this one, and it keeps transaction signatures distinct
+/// from anything else signed with the same key.
+pub const SIGNING_TAG: &[u8] = b"bitcoin/transaction/v1";
+
+/// Write the payload that is signed for `raw` to `buffer`: the tag followed by the canonical
+/// encoding of `raw`, which includes the chain ID.
+fn signing_payload(raw: &RawTransaction, buffer: &mut Vec<u8>) {
+    buffer.clear();
+    buffer.extend_from_slice(SIGNING_TAG);
+    buffer.push(ENCODING_VERSION);
+    raw.encode_to(buffer);
 }
 
 /// A signed transaction
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct SignedTransaction {
     // to avoid name confusion, we recommend renaming `Transaction` to `RawTransaction`:
-    pub raw: RawTransaction,  
-    pub pub_key: Vec<u8>,
-    pub signature: Vec<u8>,
+    pub raw: RawTransaction,
+    pub witness: Witness,
 }
-/* Please add the following code snippet into `src/transaction.rs`: */
 impl Hashable for SignedTransaction {
+    /// Hash the canonical encoding of the transaction, witness included.
     fn hash(&self) -> H256 {
-        let bytes = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+        ring::digest::digest(&ring::digest::SHA256, &self.encode()).into()
+    }
+}
+
+/// The proof that the owner of `from_addr` authorized a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Witness {
+    /// A signature by the key whose address is `from_addr`
+    Single { pub_key: Vec<u8>, signature: Vec<u8> },
+    /// Signatures by exactly `threshold` keys of the multisig account `from_addr`, in increasing
+    /// key order, so that relayers cannot add, drop or reorder signatures and change the txid
+    Multisig { policy: MultisigPolicy, signatures: Vec<KeySignature> },
+}
+
+impl Default for Witness {
+    fn default() -> Self {
+        Witness::Single { pub_key: vec![], signature: vec![] }
+    }
+}
+
+/// Maximum number of keys of a multisig account
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// An M-of-N multisig account: any `threshold` of the `pub_keys` may spend from it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    pub threshold: u8,
+    pub pub_keys: Vec<Vec<u8>>,
+}
+
+/// A signature by the key at `index` in the policy's key list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeySignature {
+    pub index: u8,
+    pub signature: Vec<u8>,
+}
+
+impl MultisigPolicy {
+    /// A policy with `threshold` of `pub_keys`, or an error if it can never be satisfied or has
+    /// invalid keys.
+    pub fn new(threshold: u8, pub_keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        let policy = MultisigPolicy { threshold, pub_keys };
+        policy.check()?;
+        Ok(policy)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let count = self.pub_keys.len();
+        if count == 0 || count > MAX_MULTISIG_KEYS {
+            return Err(format!("a multisig account needs 1 to {} keys, not {}", MAX_MULTISIG_KEYS, count));
+        }
+        if self.threshold == 0 || self.threshold as usize > count {
+            return Err(format!("the threshold must be between 1 and {}, not {}", count, self.threshold));
+        }
+        if let Some(key) = self.pub_keys.iter().find(|key| key.len() != 32) {
+            return Err(format!("public key {} is not 32 bytes", hex::encode(key)));
+        }
+        for (i, key) in self.pub_keys.iter().enumerate() {
+            if self.pub_keys[..i].contains(key) {
+                return Err(format!("public key {} appears twice", hex::encode(key)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The address of the account. It commits to the threshold and to the keys in order, and is
+    /// computed over a tagged preimage so that it cannot collide with a single-key address.
+    pub fn address(&self) -> H160 {
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        ctx.update(b"multisig");
+        ctx.update(&[self.threshold, self.pub_keys.len() as u8]);
+        for key in &self.pub_keys {
+            ctx.update(key);
+        }
+        let digest = ctx.finish();
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&digest.as_ref()[12..]);
+        address.into()
+    }
+
+    /// The index of `pub_key` in the key list.
+    pub fn index_of(&self, pub_key: &[u8]) -> Option<u8> {
+        self.pub_keys.iter().position(|key| key == pub_key).map(|index| index as u8)
+    }
+}
+
+impl Encode for RawTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.from_addr.encode_to(out);
+        self.to_addr.encode_to(out);
+        self.value.encode_to(out);
+        self.nonce.encode_to(out);
+        self.chain_id.encode_to(out);
+        self.expiry_height.encode_to(out);
+    }
+}
+
+impl Decode for RawTransaction {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(RawTransaction {
+            from_addr: Decode::decode_from(reader)?,
+            to_addr: Decode::decode_from(reader)?,
+            value: Decode::decode_from(reader)?,
+            nonce: Decode::decode_from(reader)?,
+            chain_id: Decode::decode_from(reader)?,
+            expiry_height: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for MultisigPolicy {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.threshold.encode_to(out);
+        self.pub_keys.encode_to(out);
+    }
+}
+
+impl Decode for MultisigPolicy {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(MultisigPolicy {
+            threshold: Decode::decode_from(reader)?,
+            pub_keys: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for KeySignature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.index.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for KeySignature {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(KeySignature {
+            index: Decode::decode_from(reader)?,
+            signature: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Witness {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Witness::Single { pub_key, signature } => {
+                out.push(0);
+                pub_key.encode_to(out);
+                signature.encode_to(out);
+            }
+            Witness::Multisig { policy, signatures } => {
+                out.push(1);
+                policy.encode_to(out);
+                signatures.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Witness {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match u8::decode_from(reader)? {
+            0 => Ok(Witness::Single {
+                pub_key: Decode::decode_from(reader)?,
+                signature: Decode::decode_from(reader)?,
+            }),
+            1 => Ok(Witness::Multisig {
+                policy: Decode::decode_from(reader)?,
+                signatures: Decode::decode_from(reader)?,
+            }),
+            tag => Err(DecodeError::InvalidTag { what: "witness", tag }),
+        }
+    }
+}
+
+impl Encode for SignedTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.raw.encode_to(out);
+        self.witness.encode_to(out);
+    }
+}
+
+impl Decode for SignedTransaction {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(SignedTransaction {
+            raw: Decode::decode_from(reader)?,
+            witness: Decode::decode_from(reader)?,
+        })
     }
 }
 
@@ -32,53 +236,247 @@ impl SignedTransaction {
     pub fn from_raw(raw: RawTransaction, key: &Ed25519KeyPair) -> SignedTransaction {
         let pub_key = key.public_key().as_ref().to_vec();
         let signature = sign(&raw, key).as_ref().to_vec();
-        SignedTransaction { raw, pub_key, signature }
+        SignedTransaction { raw, witness: Witness::Single { pub_key, signature } }
     }
 
-    /// Verify the signature of this transaction
+    /// Create a new transaction from a multisig account, signed by `keys`. Keys that are not in
+    /// the policy are ignored.
+    pub fn from_multisig(raw: RawTransaction, policy: MultisigPolicy, keys: &[&Ed25519KeyPair]) -> SignedTransaction {
+        let mut transaction = SignedTransaction {
+            raw,
+            witness: Witness::Multisig { policy, signatures: vec![] },
+        };
+        transaction.add_signatures(keys);
+        transaction
+    }
+
+    /// Add the signatures of `keys` to a multisig transaction, so that co-signers can sign one
+    /// after the other. Keys that are not in the policy or have signed already are ignored, and so
+    /// are keys once `threshold` keys have signed. Return the number of signatures added.
+    pub fn add_signatures(&mut self, keys: &[&Ed25519KeyPair]) -> usize {
+        let raw = &self.raw;
+        let (policy, signatures) = match &mut self.witness {
+            Witness::Multisig { policy, signatures } => (policy, signatures),
+            Witness::Single { .. } => return 0,
+        };
+        let before = signatures.len();
+        for key in keys {
+            if signatures.len() == policy.threshold as usize {
+                break;
+            }
+            if let Some(index) = policy.index_of(key.public_key().as_ref()) {
+                if signatures.iter().all(|signature| signature.index != index) {
+                    signatures.push(KeySignature { index, signature: sign(raw, key).as_ref().to_vec() });
+                }
+            }
+        }
+        signatures.sort_by_key(|signature| signature.index);
+        signatures.len() - before
+    }
+
+    /// The address of the key or key set that signed this transaction
+    pub fn signer(&self) -> H160 {
+        match &self.witness {
+            Witness::Single { pub_key, .. } => H160::from_pubkey(pub_key),
+            Witness::Multisig { policy, .. } => policy.address(),
+        }
+    }
+
+    /// Verify the signature of this transaction. A multisig transaction needs a valid policy and
+    /// valid signatures by exactly `threshold` distinct keys, in increasing key order.
     pub fn verify_signature(&self) -> bool {
-        let serialized_raw = bincode::serialize(&self.raw).unwrap();
-        let public_key = ring::signature::UnparsedPublicKey::new(
-            &ring::signature::ED25519, &self.pub_key[..]);
-        public_key.verify(&serialized_raw, self.signature.as_ref()).is_ok()
+        self.verify_with_buffer(&mut Vec::new())
+    }
+
+    /// `verify_signature`, writing the signed payload into `buffer` so that batches reuse one
+    /// allocation.
+    fn verify_with_buffer(&self, buffer: &mut Vec<u8>) -> bool {
+        signing_payload(&self.raw, buffer);
+        let payload = &buffer[..];
+        let verify = |pub_key: &[u8], signature: &[u8]| {
+            ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, pub_key)
+                .verify(payload, signature)
+                .is_ok()
+        };
+        match &self.witness {
+            Witness::Single { pub_key, signature } => verify(pub_key, signature),
+            Witness::Multisig { policy, signatures } => {
+                policy.check().is_ok()
+                    && signatures.len() == policy.threshold as usize
+                    && signatures.windows(2).all(|pair| pair[0].index < pair[1].index)
+                    && signatures.iter().all(|signature| {
+                        policy
+                            .pub_keys
+                            .get(signature.index as usize)
+                            .is_some_and(|pub_key| verify(pub_key, &signature.signature))
+                    })
+            }
+        }
     }
 }
 
+/// Batches with fewer transactions per available thread are verified on the calling thread, since
+/// spawning threads would cost more than it saves.
+const MIN_BATCH_PER_THREAD: usize = 16;
+
+/// Verify the signatures of a batch of transactions, such as the content of a block or of a
+/// `Transactions` message, spread across the available cores. Return whether each transaction's
+/// signature is valid, in order.
+pub fn verify_batch(transactions: &[SignedTransaction]) -> Vec<bool> {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    verify_in_threads(transactions, cores.min(transactions.len() / MIN_BATCH_PER_THREAD))
+}
+
+fn verify_in_threads(transactions: &[SignedTransaction], threads: usize) -> Vec<bool> {
+    let verify_chunk = |chunk: &[SignedTransaction]| {
+        let mut buffer = Vec::new();
+        chunk.iter().map(|tx| tx.verify_with_buffer(&mut buffer)).collect::<Vec<bool>>()
+    };
+    if threads <= 1 {
+        return verify_chunk(transactions);
+    }
+    let chunk_size = transactions.len().div_ceil(threads);
+    crossbeam::thread::scope(|scope| {
+        let handles: Vec<_> = transactions
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move |_| verify_chunk(chunk)))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+    .unwrap()
+}
+
 /// Create digital signature of a transaction
 pub fn sign(t: &RawTransaction, key: &Ed25519KeyPair) -> Signature {
-    let content = bincode::serialize(t).unwrap();
+    let mut content = Vec::new();
+    signing_payload(t, &mut content);
     key.sign(&content)
 }
 
 /// Verify digital signature of a transaction, using public key instead of secret key
 pub fn verify(t: &RawTransaction, public_key: &<Ed25519KeyPair as KeyPair>::PublicKey, signature: &Signature) -> bool {
-    // public_key.verify(&[t.input.clone(), t.output.clone()].concat(), signautre.as_ref()).is_ok()
+    let mut content = Vec::new();
+    signing_payload(t, &mut content);
     let _public_key = UnparsedPublicKey::new(&EdDSAParameters, public_key);
-    _public_key.verify(bincode::serialize(t).unwrap().as_ref(), signature.as_ref()).is_ok()
+    _public_key.verify(&content, signature.as_ref()).is_ok()
 }
 
 
-use crate::crypto::hash::H256;
-/* Please add the following code snippet into `src/transaction.rs`: */
-impl Hashable for RawTransaction {
-    fn hash(&self) -> H256 {
-        let bytes = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &bytes).into()
-    }
-}
-
 
-#[cfg(any(test, test_utilities))]
+#[cfg(any(test, feature = "test-utilities"))]
 mod tests {
     use super::*;
     use crate::crypto::key_pair;
 
     pub fn generate_random_transaction() -> RawTransaction {
         RawTransaction {
-            from_addr: H160::from_pubkey(&key_pair::random().public_key().as_ref()),
-            to_addr: H160::from_pubkey(&key_pair::random().public_key().as_ref()),
+            from_addr: H160::from_pubkey(key_pair::random().public_key().as_ref()),
+            to_addr: H160::from_pubkey(key_pair::random().public_key().as_ref()),
             value: rand::random::<u64>(),
             nonce: rand::random::<u32>(),
+            chain_id: Default::default(),
+            expiry_height: None,
+        }
+    }
+
+    fn multisig_transfer(keys: &[Ed25519KeyPair], signers: &[usize]) -> SignedTransaction {
+        let pub_keys = keys.iter().map(|key| key.public_key().as_ref().to_vec()).collect();
+        let policy = MultisigPolicy::new(2, pub_keys).unwrap();
+        let mut raw = generate_random_transaction();
+        raw.from_addr = policy.address();
+        let signers: Vec<&Ed25519KeyPair> = signers.iter().map(|i| &keys[*i]).collect();
+        SignedTransaction::from_multisig(raw, policy, &signers)
+    }
+
+    #[test]
+    fn multisig_verify() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let tx = multisig_transfer(&keys, &[2, 0]);
+        assert!(tx.verify_signature());
+        assert_eq!(tx.signer(), tx.raw.from_addr);
+        // signing stops at the threshold
+        assert!(multisig_transfer(&keys, &[0, 1, 2]).verify_signature());
+        assert!(multisig_transfer(&keys, &[1, 0]).verify_signature());
+        // too few signatures, also when one key signs twice
+        assert!(!multisig_transfer(&keys, &[1]).verify_signature());
+        assert!(!multisig_transfer(&keys, &[1, 1]).verify_signature());
+        let mut partial = multisig_transfer(&keys, &[1]);
+        assert_eq!(partial.add_signatures(&[&keys[1], &keys[2]]), 1);
+        assert!(partial.verify_signature());
+
+        let mut duplicated = tx.clone();
+        if let Witness::Multisig { signatures, .. } = &mut duplicated.witness {
+            signatures[1] = signatures[0].clone();
+        }
+        assert!(!duplicated.verify_signature());
+        // surplus or reordered signatures would change the txid, so they are rejected
+        let mut surplus = tx.clone();
+        if let Witness::Multisig { signatures, .. } = &mut surplus.witness {
+            signatures.insert(1, KeySignature { index: 1, signature: sign(&surplus.raw, &keys[1]).as_ref().to_vec() });
+        }
+        assert!(!surplus.verify_signature());
+        let mut reordered = tx.clone();
+        if let Witness::Multisig { signatures, .. } = &mut reordered.witness {
+            signatures.swap(0, 1);
+        }
+        assert!(!reordered.verify_signature());
+        let mut forged = tx.clone();
+        forged.raw.value = forged.raw.value.wrapping_add(1);
+        assert!(!forged.verify_signature());
+
+        // the address commits to the threshold and to the keys
+        let pub_keys: Vec<Vec<u8>> = keys.iter().map(|key| key.public_key().as_ref().to_vec()).collect();
+        let two_of_three = MultisigPolicy::new(2, pub_keys.clone()).unwrap();
+        assert_ne!(two_of_three.address(), MultisigPolicy::new(3, pub_keys.clone()).unwrap().address());
+        assert_ne!(two_of_three.address(), MultisigPolicy::new(2, pub_keys[..2].to_vec()).unwrap().address());
+        assert!(MultisigPolicy::new(4, pub_keys.clone()).is_err());
+        assert!(MultisigPolicy::new(0, pub_keys.clone()).is_err());
+        assert!(MultisigPolicy::new(1, vec![pub_keys[0].clone(), pub_keys[0].clone()]).is_err());
+    }
+
+    fn signed_batch(count: usize) -> Vec<SignedTransaction> {
+        let key = key_pair::random();
+        (0..count)
+            .map(|_| SignedTransaction::from_raw(generate_random_transaction(), &key))
+            .collect()
+    }
+
+    #[test]
+    fn batch_verify() {
+        let mut transactions = signed_batch(100);
+        transactions[3].raw.value = transactions[3].raw.value.wrapping_add(1);
+        transactions[97].raw.nonce = transactions[97].raw.nonce.wrapping_add(1);
+        let expected: Vec<bool> = transactions.iter().map(SignedTransaction::verify_signature).collect();
+        assert_eq!(verify_batch(&transactions), expected);
+        assert_eq!(verify_in_threads(&transactions, 3), expected);
+        assert_eq!(expected.iter().filter(|valid| !**valid).count(), 2);
+        assert_eq!(verify_batch(&transactions[..2]), vec![true, true]);
+        assert!(verify_batch(&[]).is_empty());
+    }
+
+    /// Compare per-transaction and batch verification. Run with
+    /// `cargo test --release verify_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn verify_benchmark() {
+        use std::time::Instant;
+
+        for count in &[10, 100, 1000, 10_000] {
+            let transactions = signed_batch(*count);
+            let start = Instant::now();
+            let single: Vec<bool> = transactions.iter().map(SignedTransaction::verify_signature).collect();
+            let single_time = start.elapsed();
+            let start = Instant::now();
+            let batch = verify_batch(&transactions);
+            let batch_time = start.elapsed();
+            assert_eq!(single, batch);
+            println!(
+                "{:>6} transactions: per transaction {:>10.3?}, batch {:>10.3?}, speedup {:.2}x",
+                count,
+                single_time,
+                batch_time,
+                single_time.as_secs_f64() / batch_time.as_secs_f64()
+            );
         }
     }
 
@@ -91,7 +489,24 @@ mod tests {
         // casting then printing
         // get length of signature
         let _length = signature.as_ref().to_vec().len();
-        assert!(verify(&t, &(key.public_key()), &signature));
+        assert!(verify(&t, key.public_key(), &signature));
+    }
+
+    #[test]
+    fn signatures_are_bound_to_the_tag_and_chain() {
+        let t = generate_random_transaction();
+        let key = key_pair::random();
+        let tx = SignedTransaction::from_raw(t.clone(), &key);
+        assert!(tx.verify_signature());
+        // a signature over the bare encoding, as before the signing tag, is rejected
+        let bare = key.sign(&bincode::serialize(&t).unwrap());
+        assert!(!verify(&t, key.public_key(), &bare));
+        let mut other_chain = tx.clone();
+        other_chain.raw.chain_id = [1u8; 32].into();
+        assert!(!other_chain.verify_signature());
+        let mut extended = tx;
+        extended.raw.expiry_height = Some(10);
+        assert!(!extended.verify_signature());
     }
 
 }
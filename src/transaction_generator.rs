@@ -1,22 +1,20 @@
 use log::{debug, warn};
 use rand::Rng;
-use serde::{Serialize,Deserialize};
-use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters};
+use ring::signature::{Ed25519KeyPair, KeyPair};
 use crate::address::H160;
 use crate::crypto::hash::{H256, Hashable};
 
 use crate::crypto::key_pair;
-use crate::network::server::Handle as ServerHandle;
+use crate::network::inventory::Handle as InventoryHandle;
 use crate::transaction::{RawTransaction, SignedTransaction as Transaction};
 use std::thread;
 use std::time;
 use std::sync::{Arc, Mutex};
 use crate::mempool::Mempool;
-use crate::network::message::Message;
 use crate::blockchain::{Blockchain};
 
 pub struct TransactionGenerator {
-    server: ServerHandle,
+    inventory: InventoryHandle,
     mempool: Arc<Mutex<Mempool>>,
     blockchain: Arc<Mutex<Blockchain>>,
     controlled_keypair: Ed25519KeyPair,
@@ -24,13 +22,13 @@ pub struct TransactionGenerator {
 
 impl TransactionGenerator {
     pub fn new(
-        server: &ServerHandle,
+        inventory: &InventoryHandle,
         mempool: &Arc<Mutex<Mempool>>,
         blockchain: &Arc<Mutex<Blockchain>>,
         controlled_keypair: Ed25519KeyPair
     ) -> TransactionGenerator {
         TransactionGenerator {
-            server: server.clone(),
+            inventory: inventory.clone(),
             mempool: Arc::clone(mempool),
             blockchain: Arc::clone(blockchain),
             controlled_keypair,
@@ -50,6 +48,7 @@ impl TransactionGenerator {
         const INTERVAL_MILLISECONDS: u64 = 3000; // how quickly to generate transactions
 
         let sender = self.controlled_keypair.public_key().as_ref();
+        let chain_id = self.blockchain.lock().unwrap().chain_id();
 
         let mut rng = rand::thread_rng();
 
@@ -74,10 +73,12 @@ impl TransactionGenerator {
                 let transaction = Transaction::from_raw(
                     RawTransaction {
                         from_addr: H160::from_pubkey(sender),
-                        to_addr: H160::from_pubkey(&key_pair::random().public_key().as_ref()),
+                        to_addr: H160::from_pubkey(key_pair::random().public_key().as_ref()),
                         // positive value
                         value,
                         nonce: sender_nonce + 1,
+                        chain_id,
+                        expiry_height: None,
                     },
                     &self.controlled_keypair,
                 );
@@ -87,12 +88,12 @@ impl TransactionGenerator {
                     continue;
                 }
 
-                if transaction.raw.from_addr != H160::from_pubkey(&transaction.pub_key) {
-                    warn!("Invalid transaction detected: Failed to match from_addr: {:?} with address of pub_key: {:?}", transaction.raw.from_addr, H160::from_pubkey(&transaction.pub_key));
+                if transaction.raw.from_addr != transaction.signer() {
+                    warn!("Invalid transaction detected: Failed to match from_addr: {:?} with address of pub_key: {:?}", transaction.raw.from_addr, transaction.signer());
                     continue;
                 }
 
-                let sender = H160::from_pubkey(&transaction.pub_key);
+                let sender = transaction.signer();
                 let block_hash = self.blockchain.lock().unwrap().tip();  // tip of the blockchain
                 // get the sender's nonce and balance
                 let blockchain = self.blockchain.lock().unwrap();
@@ -128,10 +129,10 @@ impl TransactionGenerator {
             // debug!("# of valid transactions in mempool: {}", mempool.get_valid_transactions(&self.blockchain.lock().unwrap().state()).len());  // deadlock here?
 
             
-            // 3. broadcast them using `self.server.broadcast(Message::NewTransactionHashes(...))`:
-            if transactions.len() > 0 {
+            // 3. announce them to the peers (as `Message::NewTransactionHashes(...)`):
+            if !transactions.is_empty() {
                 let hashes: Vec<H256> = transactions.clone().iter().map(|tx| tx.hash()).collect();
-                self.server.broadcast(Message::NewTransactionHashes(hashes));
+                self.inventory.announce_transactions(hashes);
             }
         }
     }
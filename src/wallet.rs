@@ -0,0 +1,434 @@
+use crate::address::H160;
+use crate::blockchain::State;
+use crate::crypto::hd::{self, DerivationPath, ExtendedKey};
+use crate::mempool::Mempool;
+use crate::transaction::{MultisigPolicy, RawTransaction, SignedTransaction, Witness};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+/// Version of the keystore file format.
+const KEYSTORE_VERSION: u32 = 1;
+/// PBKDF2-HMAC-SHA256 iterations used to derive the keystore encryption key from the passphrase.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Errors of wallet operations.
+#[derive(Debug)]
+pub enum WalletError {
+    Io(std::io::Error),
+    /// The keystore file is malformed
+    Corrupt(String),
+    /// The keystore could not be decrypted, usually because of a wrong passphrase
+    WrongPassphrase,
+    /// The seed is not a valid Ed25519 seed
+    InvalidSeed(String),
+    /// The wallet has no key for the address
+    UnknownAddress(H160),
+    /// The account cannot afford the transfer
+    InsufficientBalance { balance: u64, value: u64 },
+    /// The wallet holds none of the keys of a multisig account
+    NotACosigner(H160),
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "keystore I/O error: {}", e),
+            WalletError::Corrupt(msg) => write!(f, "corrupt keystore: {}", msg),
+            WalletError::WrongPassphrase => write!(f, "cannot decrypt keystore, wrong passphrase?"),
+            WalletError::InvalidSeed(msg) => write!(f, "invalid seed: {}", msg),
+            WalletError::UnknownAddress(address) => write!(f, "no key for address {}", address),
+            WalletError::InsufficientBalance { balance, value } => {
+                write!(f, "balance {} is less than the value {}", balance, value)
+            }
+            WalletError::NotACosigner(address) => write!(f, "no key of multisig account {}", address),
+        }
+    }
+}
+
+impl From<std::io::Error> for WalletError {
+    fn from(e: std::io::Error) -> Self {
+        WalletError::Io(e)
+    }
+}
+
+/// The encrypted keystore file. The plaintext is the JSON list of the keys.
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    /// Hex-encoded 32-byte Ed25519 seed
+    seed: String,
+}
+
+/// An Ed25519 key of the wallet, kept as its seed.
+#[derive(Clone)]
+struct WalletKey {
+    seed: [u8; 32],
+    address: H160,
+}
+
+impl WalletKey {
+    fn from_seed(seed: [u8; 32]) -> Result<Self, WalletError> {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&seed)
+            .map_err(|e| WalletError::InvalidSeed(e.to_string()))?;
+        Ok(WalletKey {
+            seed,
+            address: H160::from_pubkey(key_pair.public_key().as_ref()),
+        })
+    }
+
+    fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.seed).unwrap()
+    }
+}
+
+/// Where and how a wallet is persisted.
+struct Keystore {
+    path: PathBuf,
+    passphrase: String,
+}
+
+/// A set of Ed25519 keys, optionally persisted in an encrypted keystore file.
+#[derive(Default)]
+pub struct Wallet {
+    keys: Vec<WalletKey>,
+    keystore: Option<Keystore>,
+}
+
+/// Parse a hex-encoded 32-byte seed.
+pub fn parse_seed(hex_seed: &str) -> Result<[u8; 32], WalletError> {
+    let mut seed = [0u8; 32];
+    hex::decode_to_slice(hex_seed.trim(), &mut seed).map_err(|e| WalletError::InvalidSeed(e.to_string()))?;
+    Ok(seed)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey, WalletError> {
+    let iterations =
+        NonZeroU32::new(iterations).ok_or_else(|| WalletError::Corrupt("zero iterations".to_string()))?;
+    let mut key = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    Ok(LessSafeKey::new(UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap()))
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>, WalletError> {
+    hex::decode(value).map_err(|e| WalletError::Corrupt(format!("{}: {}", field, e)))
+}
+
+impl Wallet {
+    /// An empty wallet that is not persisted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the keystore at `path`, or start an empty wallet if the file does not exist. Changes
+    /// are written back to the keystore.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        let keys = if path.exists() {
+            Self::read_keystore(path, passphrase)?
+        } else {
+            vec![]
+        };
+        Ok(Wallet {
+            keys,
+            keystore: Some(Keystore {
+                path: path.to_path_buf(),
+                passphrase: passphrase.to_string(),
+            }),
+        })
+    }
+
+    fn read_keystore(path: &Path, passphrase: &str) -> Result<Vec<WalletKey>, WalletError> {
+        let file: KeystoreFile = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| WalletError::Corrupt(e.to_string()))?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(WalletError::Corrupt(format!("unsupported version {}", file.version)));
+        }
+        let salt = decode_hex("salt", &file.salt)?;
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        hex::decode_to_slice(&file.nonce, &mut nonce).map_err(|e| WalletError::Corrupt(format!("nonce: {}", e)))?;
+        let mut ciphertext = decode_hex("ciphertext", &file.ciphertext)?;
+        let key = derive_key(passphrase, &salt, file.iterations)?;
+        let plaintext = key
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut ciphertext)
+            .map_err(|_| WalletError::WrongPassphrase)?;
+        let stored: Vec<StoredKey> =
+            serde_json::from_slice(plaintext).map_err(|e| WalletError::Corrupt(e.to_string()))?;
+        stored
+            .iter()
+            .map(|key| WalletKey::from_seed(parse_seed(&key.seed)?))
+            .collect()
+    }
+
+    /// Write `keys` to the keystore of the wallet, if it has one. A fresh salt and nonce are used
+    /// every time.
+    fn persist(&self, keys: &[WalletKey]) -> Result<(), WalletError> {
+        let keystore = match &self.keystore {
+            Some(keystore) => keystore,
+            None => return Ok(()),
+        };
+        let stored: Vec<StoredKey> = keys
+            .iter()
+            .map(|key| StoredKey { seed: hex::encode(key.seed) })
+            .collect();
+        let mut in_out = serde_json::to_vec(&stored).unwrap();
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rng.fill(&mut salt).unwrap();
+        rng.fill(&mut nonce).unwrap();
+        let key = derive_key(&keystore.passphrase, &salt, PBKDF2_ITERATIONS)?;
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut in_out)
+            .unwrap();
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            iterations: PBKDF2_ITERATIONS,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(in_out),
+        };
+        // write to a temporary file first so that a crash never leaves a truncated keystore
+        let tmp = keystore.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&file).unwrap())?;
+        std::fs::rename(&tmp, &keystore.path)?;
+        Ok(())
+    }
+
+    /// Generate a new random key and return its address.
+    pub fn generate(&mut self) -> Result<H160, WalletError> {
+        let mut seed = [0u8; 32];
+        SystemRandom::new().fill(&mut seed).unwrap();
+        self.import(seed)
+    }
+
+    /// Add the key with the given seed and return its address. Importing a known key is a no-op.
+    pub fn import(&mut self, seed: [u8; 32]) -> Result<H160, WalletError> {
+        let key = WalletKey::from_seed(seed)?;
+        let address = key.address;
+        if !self.contains(&address) {
+            // only keep the key once it is in the keystore
+            let mut keys = self.keys.clone();
+            keys.push(key);
+            self.persist(&keys)?;
+            self.keys = keys;
+        }
+        Ok(address)
+    }
+
+    /// Add the key at `path` of the seed of `phrase` and `passphrase`, and return its address.
+    pub fn import_derived(&mut self, phrase: &str, passphrase: &str, path: &DerivationPath) -> Result<H160, WalletError> {
+        let seed = hd::seed_from_phrase(phrase, passphrase);
+        self.import(ExtendedKey::master(&seed).derive(path).key)
+    }
+
+    /// The seed of the key of `address`.
+    pub fn export(&self, address: &H160) -> Result<[u8; 32], WalletError> {
+        self.key(address).map(|key| key.seed)
+    }
+
+    pub fn contains(&self, address: &H160) -> bool {
+        self.keys.iter().any(|key| key.address == *address)
+    }
+
+    /// Addresses of all keys, in the order they were added.
+    pub fn addresses(&self) -> Vec<H160> {
+        self.keys.iter().map(|key| key.address).collect()
+    }
+
+    fn key(&self, address: &H160) -> Result<&WalletKey, WalletError> {
+        self.keys
+            .iter()
+            .find(|key| key.address == *address)
+            .ok_or(WalletError::UnknownAddress(*address))
+    }
+
+    /// The public key of the key of `address`.
+    pub fn public_key(&self, address: &H160) -> Result<Vec<u8>, WalletError> {
+        self.key(address).map(|key| key.key_pair().public_key().as_ref().to_vec())
+    }
+
+    /// Build and sign a transfer from `from` to `to`, using the next nonce of `from` in `state`
+    /// after its transactions pending in `mempool`.
+    /// The transfer cannot be included above `expiry_height`, if given.
+    pub fn build_transaction(
+        &self,
+        state: &State,
+        mempool: &Mempool,
+        from: &H160,
+        to: &H160,
+        value: u64,
+        expiry_height: Option<u64>,
+    ) -> Result<SignedTransaction, WalletError> {
+        let key = self.key(from)?;
+        let raw = next_transfer(state, mempool, from, to, value, expiry_height)?;
+        Ok(SignedTransaction::from_raw(raw, &key.key_pair()))
+    }
+
+    /// Build a transfer from the multisig account of `policy` to `to`, signed by the wallet's keys
+    /// of the policy. It needs more signatures if the wallet holds fewer than `threshold` keys.
+    pub fn build_multisig_transaction(
+        &self,
+        state: &State,
+        mempool: &Mempool,
+        policy: MultisigPolicy,
+        to: &H160,
+        value: u64,
+        expiry_height: Option<u64>,
+    ) -> Result<SignedTransaction, WalletError> {
+        let raw = next_transfer(state, mempool, &policy.address(), to, value, expiry_height)?;
+        let mut transaction = SignedTransaction::from_multisig(raw, policy, &[]);
+        self.sign_multisig(&mut transaction)?;
+        Ok(transaction)
+    }
+
+    /// Add the signatures of the wallet's keys to a multisig transaction, and return how many
+    /// were added.
+    pub fn sign_multisig(&self, transaction: &mut SignedTransaction) -> Result<usize, WalletError> {
+        let key_pairs: Vec<Ed25519KeyPair> = self.keys.iter().map(WalletKey::key_pair).collect();
+        let held = key_pairs.iter().filter(|key| match &transaction.witness {
+            Witness::Multisig { policy, .. } => policy.index_of(key.public_key().as_ref()).is_some(),
+            Witness::Single { .. } => false,
+        });
+        let held: Vec<&Ed25519KeyPair> = held.collect();
+        if held.is_empty() {
+            return Err(WalletError::NotACosigner(transaction.raw.from_addr));
+        }
+        Ok(transaction.add_signatures(&held))
+    }
+}
+
+/// An unsigned transfer on the chain of `state` with the next nonce of `from`, if `from` can
+/// afford it. The transactions of `from` pending in `mempool` come first.
+fn next_transfer(
+    state: &State,
+    mempool: &Mempool,
+    from: &H160,
+    to: &H160,
+    value: u64,
+    expiry_height: Option<u64>,
+) -> Result<RawTransaction, WalletError> {
+    let (nonce, balance) = mempool.pending_account(state, from, u32::MAX);
+    if balance < value {
+        return Err(WalletError::InsufficientBalance { balance, value });
+    }
+    Ok(RawTransaction {
+        from_addr: *from,
+        to_addr: *to,
+        value,
+        nonce: nonce + 1,
+        chain_id: state.chain_id(),
+        expiry_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{ico_path, DEFAULT_GENESIS_PHRASE};
+    use crate::blockchain::Blockchain;
+    use crate::mempool;
+
+    fn keystore_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wallet-test-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn keystore_roundtrip() {
+        let path = keystore_path("roundtrip");
+        let mut wallet = Wallet::open(&path, "hunter2").unwrap();
+        let generated = wallet.generate().unwrap();
+        let imported = wallet.import([1u8; 32]).unwrap();
+        assert_eq!(wallet.import([1u8; 32]).unwrap(), imported);
+        assert_eq!(wallet.addresses(), vec![generated, imported]);
+
+        let reopened = Wallet::open(&path, "hunter2").unwrap();
+        assert_eq!(reopened.addresses(), vec![generated, imported]);
+        assert_eq!(reopened.export(&imported).unwrap(), [1u8; 32]);
+        assert!(matches!(Wallet::open(&path, "hunter3"), Err(WalletError::WrongPassphrase)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn builds_valid_transactions() {
+        let mut wallet = Wallet::new();
+        let from = wallet
+            .import_derived(DEFAULT_GENESIS_PHRASE, "", &ico_path(0))
+            .unwrap();
+        let to = wallet.generate().unwrap();
+        let state = Blockchain::new().state();
+        let mut pending = Mempool::new();
+        let tx = wallet.build_transaction(&state, &pending, &from, &to, 10, None).unwrap();
+        assert_eq!(tx.raw.nonce, state.get(&from).unwrap().0 + 1);
+        assert_eq!(tx.raw.chain_id, state.chain_id());
+        assert!(mempool::validate(&state, &tx).is_ok());
+        assert!(matches!(
+            wallet.build_transaction(&state, &pending, &to, &from, 1, None),
+            Err(WalletError::InsufficientBalance { balance: 0, value: 1 })
+        ));
+        assert!(matches!(
+            wallet.build_transaction(&state, &pending, &H160::default(), &from, 1, None),
+            Err(WalletError::UnknownAddress(_))
+        ));
+
+        // the next transfer follows the pending one
+        let (_, balance) = *state.get(&from).unwrap();
+        pending.insert(tx.clone());
+        let next = wallet.build_transaction(&state, &pending, &from, &to, 20, None).unwrap();
+        assert_eq!(next.raw.nonce, tx.raw.nonce + 1);
+        assert_eq!(pending.validate_pending(&state, &next), Ok(()));
+        assert!(matches!(
+            wallet.build_transaction(&state, &pending, &from, &to, balance, None),
+            Err(WalletError::InsufficientBalance { value, .. }) if value == balance
+        ));
+    }
+
+    #[test]
+    fn failed_import_keeps_keys() {
+        let path = std::env::temp_dir()
+            .join(format!("wallet-test-missing-{}", std::process::id()))
+            .join("keystore.json");
+        let mut wallet = Wallet::open(&path, "hunter2").unwrap();
+        assert!(wallet.import([1u8; 32]).is_err());
+        assert!(wallet.generate().is_err());
+        assert!(wallet.addresses().is_empty());
+    }
+
+    #[test]
+    fn cosigns_multisig_transactions() {
+        let mut wallets: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let pub_keys = wallets
+            .iter_mut()
+            .map(|wallet| {
+                let address = wallet.generate().unwrap();
+                wallet.public_key(&address).unwrap()
+            })
+            .collect();
+        let policy = MultisigPolicy::new(2, pub_keys).unwrap();
+        let raw = RawTransaction {
+            from_addr: policy.address(),
+            to_addr: H160::default(),
+            value: 1,
+            nonce: 1,
+            chain_id: Default::default(),
+            expiry_height: None,
+        };
+        let mut tx = SignedTransaction::from_multisig(raw, policy, &[]);
+        assert_eq!(wallets[2].sign_multisig(&mut tx).unwrap(), 1);
+        assert_eq!(wallets[2].sign_multisig(&mut tx).unwrap(), 0);
+        assert!(!tx.verify_signature());
+        assert_eq!(wallets[0].sign_multisig(&mut tx).unwrap(), 1);
+        assert!(tx.verify_signature());
+        assert!(matches!(Wallet::new().sign_multisig(&mut tx), Err(WalletError::NotACosigner(_))));
+    }
+}
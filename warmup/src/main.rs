@@ -1,7 +1,5 @@
 // Hashing
-use hex;
 use serde::{Serialize, Deserialize};
-use bincode;
 
 
 
@@ -29,7 +27,7 @@ fn main() {
         hash: String,
     }
     let name_hash: NameHash = NameHash {
-        name: name,
+        name,
         hash: hex,
     };
 